@@ -107,10 +107,25 @@ local function entry_get_offset_dbg(self)
 	return offset
 end
 
-function M.setup()
+function M.setup(opts)
+	opts = opts or {}
 	lib.log.init({ file = "/tmp/cmp-native.log", level = "debug", terminal = false })
 	lib.log.info("Setting up nvim-cmp-native-utils")
+	if opts.matcher then
+		lib.matcher.configure(opts.matcher)
+	end
+	if opts.char_classes then
+		lib.configure_char_classes(opts.char_classes)
+	end
+	M.filter = opts.filter
 	require("cmp.entry").get_offset = entry_get_offset_dbg
+	-- keep the itemDefaults of the completion response for get_entries_from_source
+	local entry_new = require("cmp.entry").new
+	require("cmp.entry").new = function(ctx, source, completion_item, item_defaults)
+		local e = entry_new(ctx, source, completion_item, item_defaults)
+		e.item_defaults = item_defaults
+		return e
+	end
 	-- original_match = require("cmp.matcher").match
 	-- require("cmp.matcher").match = function(input, word, words)
 	-- 	local arg_words = words or {}
@@ -129,7 +144,8 @@ function M.setup()
 		-- end
 
 		local s = lib.timestamp()
-		local r = lib.get_entries_from_source(self, ctx, self:get_config().max_item_count or 200)
+		local matching = require("cmp.config").get().matching
+		local r = lib.get_entries_from_source(self, ctx, self:get_config().max_item_count or 200, matching, M.filter)
 		local e = lib.timestamp()
 
 		-- if #r > 0 then
@@ -211,9 +227,16 @@ function M.bench_matcher()
 	lib.log.info("Iter with Rust took " .. (e - start))
 
 	start = lib.timestamp()
-	lib.matcher.bench_rs()
+	local miss_heavy = lib.matcher.bench_rs()
 	e = lib.timestamp()
 	lib.log.info("pure rust took " .. (e - start))
+	lib.log.info(
+		"miss-heavy corpus took "
+			.. miss_heavy.miss_heavy
+			.. " (without prefilter "
+			.. miss_heavy.miss_heavy_without_prefilter
+			.. ")"
+	)
 end
 
 return M
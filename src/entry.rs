@@ -1,27 +1,58 @@
 use fancy_regex::Regex;
 use mlua::prelude::*;
+use std::sync::Arc;
 
 use crate::{
     context::Context,
-    models::lsp::{CompletionItem, InsertTextFormat, TextEdit},
+    models::lsp::{CompletionItem, CompletionItemDefaults, TextEdit},
+    snippet::parser,
     utils::{
         byte_char,
-        matcher::{self, MatchRegion},
+        char_class::{CharClass, CharClasses},
+        matcher::{MatchRegion, Query},
         misc, str_utils,
     },
 };
 
-#[derive(Debug)]
-pub struct Entry<'lua> {
+/// An entry of a cmp source, owned so that it can be matched off the main thread.
+#[derive(Debug, Clone)]
+pub struct EntryData {
     completion_item: CompletionItem,
     context: Context,
     source_offset: i32,
     offset: Option<i32>,
     word: Option<String>,
-    pub entry: LuaTable<'lua>,
+
+    /// The identifier chars of the filetype of the context
+    char_class: Arc<CharClass>,
+}
+
+/// The converted `item_defaults` of the last entry.
+///
+/// The entries of a completion response share their `item_defaults` table, so a source
+/// converts it once instead of once per entry.
+#[derive(Default)]
+pub struct ItemDefaultsCache<'lua> {
+    last: Option<(LuaTable<'lua>, CompletionItemDefaults)>,
+}
+
+impl<'lua> ItemDefaultsCache<'lua> {
+    fn get(&mut self, tbl: LuaTable<'lua>, lua: &'lua Lua) -> LuaResult<&CompletionItemDefaults> {
+        if !matches!(&self.last, Some((last, _)) if *last == tbl) {
+            let defaults = CompletionItemDefaults::from_lua(LuaValue::Table(tbl.clone()), lua)?;
+            self.last = Some((tbl, defaults));
+        }
+        Ok(&self.last.as_ref().unwrap().1)
+    }
 }
 
-pub fn get_completion_item(tbl: &LuaTable, lua: &Lua) -> LuaResult<CompletionItem> {
+/// The completion item of an entry table, with its resolved fields and the `item_defaults` of
+/// the completion response it came from, see `CompletionItem::from_lua_for_filtering`.
+pub fn get_completion_item<'lua>(
+    tbl: &LuaTable<'lua>,
+    lua: &'lua Lua,
+    defaults: &mut ItemDefaultsCache<'lua>,
+) -> LuaResult<CompletionItem> {
     let resolved_completion_item: Option<LuaTable> = tbl.get("resolved_completion_item")?;
     let completion_item: LuaTable = tbl.get("completion_item")?;
     let item_lua = if let Some(resolved_completion_item) = resolved_completion_item {
@@ -35,23 +66,19 @@ pub fn get_completion_item(tbl: &LuaTable, lua: &Lua) -> LuaResult<CompletionIte
     } else {
         completion_item
     };
-    CompletionItem::from_lua(LuaValue::Table(item_lua), lua)
+    let mut completion_item = CompletionItem::from_lua_for_filtering(&item_lua, lua)?;
+    if let Some(item_defaults) = tbl.get::<_, Option<LuaTable>>("item_defaults")? {
+        defaults
+            .get(item_defaults, lua)?
+            .apply(&mut completion_item);
+    }
+    Ok(completion_item)
 }
 
-impl<'lua> FromLua<'lua> for Entry<'lua> {
+impl<'lua> FromLua<'lua> for EntryData {
     fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
         match lua_value {
-            LuaValue::Table(tbl) => {
-                let completion_item = get_completion_item(&tbl, lua)?;
-                Ok(Self {
-                    completion_item,
-                    context: tbl.get("context")?,
-                    source_offset: tbl.get("source_offset")?,
-                    offset: None,
-                    word: None,
-                    entry: tbl,
-                })
-            }
+            LuaValue::Table(tbl) => Self::new(&tbl, lua, &mut ItemDefaultsCache::default()),
             LuaValue::Nil => Err(LuaError::FromLuaConversionError {
                 from: "Nil",
                 to: "Entry",
@@ -106,7 +133,26 @@ impl<'lua> FromLua<'lua> for Entry<'lua> {
     }
 }
 
-impl<'lua> Entry<'lua> {
+impl EntryData {
+    /// Converts an entry table, with the `item_defaults` cache of its source.
+    pub fn new<'lua>(
+        tbl: &LuaTable<'lua>,
+        lua: &'lua Lua,
+        defaults: &mut ItemDefaultsCache<'lua>,
+    ) -> LuaResult<Self> {
+        let completion_item = get_completion_item(tbl, lua, defaults)?;
+        let context: Context = tbl.get("context")?;
+        let char_class = CharClasses::from_app_data(lua, context.filetype.as_deref());
+        Ok(Self {
+            completion_item,
+            context,
+            source_offset: tbl.get("source_offset")?,
+            offset: None,
+            word: None,
+            char_class,
+        })
+    }
+
     pub fn get_filter_text(&self) -> &str {
         if let Some(filter_text) = &self.completion_item.filter_text {
             filter_text
@@ -114,6 +160,9 @@ impl<'lua> Entry<'lua> {
             self.completion_item.label.trim()
         }
     }
+    pub fn get_label(&self) -> &str {
+        &self.completion_item.label
+    }
     pub fn get_override(&self) -> LuaResult<(i32, i32)> {
         if let Some(text_edit) = &self.completion_item.text_edit {
             let r = if let Some(insert) = &text_edit.insert {
@@ -148,27 +197,28 @@ impl<'lua> Entry<'lua> {
 
         if let Some(text_edit) = &self.completion_item.text_edit {
             word = text_edit.new_text.trim().to_owned();
-            if self.completion_item.insert_text_format == InsertTextFormat::Snippet {
-                ::log::warn!("Snippet not implemented");
-                word = str_utils::get_word(&word, 0);
+            if self.completion_item.is_snippet() {
+                word = parser::parse(&word).to_plain_text();
             }
             let override_v = self.get_override()?;
-            if 0 < override_v.1
-                || self.completion_item.insert_text_format == InsertTextFormat::Snippet
-            {
+            if 0 < override_v.1 || self.completion_item.is_snippet() {
                 word = str_utils::get_word_with_min_len(
                     &word,
-                    self.context.cursor_after_line.as_bytes()[0],
+                    self.context.cursor_after_line.bytes().next().unwrap_or(0),
                     override_v.0 as usize,
+                    &self.char_class,
                 )
             }
         } else {
             match &self.completion_item.insert_text {
                 Some(lua_str) => {
                     word = std::str::from_utf8(lua_str.as_bytes())?.trim().to_owned();
-                    if self.completion_item.insert_text_format == InsertTextFormat::Snippet {
-                        ::log::debug!("TODO: parse snippet");
-                        word = str_utils::get_word(&word, 0);
+                    if self.completion_item.is_snippet() {
+                        word = str_utils::get_word(
+                            &parser::parse(&word).to_plain_text(),
+                            0,
+                            &self.char_class,
+                        );
                     }
                 }
                 _ => {
@@ -219,6 +269,12 @@ impl<'lua> Entry<'lua> {
                 .rev()
             {
                 let idx = byte_char::get_real_idx(self.context.cursor_line.as_bytes().len(), idx_v);
+                if !self
+                    .char_class
+                    .is_semantic_byte_index(self.context.cursor_line.as_bytes(), idx)
+                {
+                    continue;
+                }
                 let c = self.context.cursor_line.as_bytes()[idx];
                 if byte_char::is_white(c) {
                     break;
@@ -241,52 +297,144 @@ impl<'lua> Entry<'lua> {
         self.offset = Some(offset);
         Ok(offset)
     }
-    pub fn do_match(&mut self, input: &str) -> LuaResult<(f64, Vec<MatchRegion>)> {
+    pub fn do_match(&mut self, query: &Query) -> LuaResult<(f64, Vec<MatchRegion>)> {
         let filter_text = self.get_filter_text().to_owned();
         let word = self.get_word()?;
-        let (mut score, mut matches) = matcher::do_match(
-            input.as_bytes(),
-            filter_text.as_bytes(),
-            &[word.as_bytes(), self.completion_item.label.as_bytes()],
-        );
+        let (mut score, mut matches) =
+            query.do_match(&filter_text, &[&word, &self.completion_item.label]);
         if score - 0.0 < 0.0001 {
-            let offset = self.get_offset()?;
-            if let Some(text_edit) = &self.completion_item.text_edit {
-                let diff = self.source_offset - offset;
-                if diff > 0 {
-                    let prefix = &self.context.cursor_line.as_str().as_bytes()
-                        [offset as usize..self.source_offset as usize];
-                    let prefix_str = std::str::from_utf8(prefix)?;
-                    let mut accept = false;
-
-                    let prefix_pattern: Regex = Regex::new("^[^a-zA-Z]+$").unwrap();
-                    accept = accept
-                        || prefix_pattern
-                            .is_match(prefix_str)
-                            .map_err(|e| e.to_lua_err())?;
-                    accept = accept || text_edit.new_text.find(prefix_str).is_some();
-                    if accept {
-                        let (s, m) = matcher::do_match(
-                            input.as_bytes(),
-                            &format!("{}{}", prefix_str, filter_text).as_bytes(),
-                            &[word.as_bytes(), self.completion_item.label.as_bytes()],
-                        );
-                        score = s;
-                        matches = m;
-                    }
-                    // TODO: implement this
-                }
+            if let Some(text) = self.get_prefixed_filter_text()? {
+                let (s, m) = query.do_match(&text, &[&word, &self.completion_item.label]);
+                score = s;
+                matches = m;
             }
         }
         if filter_text != self.completion_item.label.as_str() {
-            let (_, m) = matcher::do_match(
-                input.as_bytes(),
-                self.completion_item.label.as_bytes(),
-                &[word.as_bytes(), self.completion_item.label.as_bytes()],
+            let (_, m) = query.do_match(
+                &self.completion_item.label,
+                &[&word, &self.completion_item.label],
             );
             matches = m;
         }
         let r = (score, matches);
         Ok(r)
     }
+
+    /// The filter text after the part of the cursor line between the offset of the entry and
+    /// the source offset, when the text edit of the entry starts before the latter and accepts
+    /// that part.
+    fn get_prefixed_filter_text(&mut self) -> LuaResult<Option<String>> {
+        let offset = self.get_offset()?;
+        let text_edit = match &self.completion_item.text_edit {
+            Some(text_edit) if self.source_offset > offset => text_edit,
+            _ => return Ok(None),
+        };
+        let prefix =
+            &self.context.cursor_line.as_bytes()[offset as usize..self.source_offset as usize];
+        let prefix_str = std::str::from_utf8(prefix)?;
+        let prefix_pattern: Regex = Regex::new("^[^a-zA-Z]+$").unwrap();
+        let accept = prefix_pattern
+            .is_match(prefix_str)
+            .map_err(|e| e.to_lua_err())?
+            || text_edit.new_text.contains(prefix_str);
+        if accept {
+            Ok(Some(format!("{}{}", prefix_str, self.get_filter_text())))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Whether a longer input may match the entry although the query doesn't, see
+    /// `Query::may_match_longer`.
+    pub fn may_match_longer(&mut self, query: &Query) -> LuaResult<bool> {
+        if query.may_match_longer(self.get_filter_text()) {
+            return Ok(true);
+        }
+        match self.get_prefixed_filter_text()? {
+            Some(text) => Ok(query.may_match_longer(&text)),
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::context::{ContextOption, ContextReason, Cursor};
+    use serde_json::json;
+
+    /// An entry of a source at the byte offset `source_offset` (1-based), with the cursor at
+    /// the end of `line`.
+    fn entry(item: serde_json::Value, line: &str, source_offset: i32) -> EntryData {
+        EntryData {
+            completion_item: serde_json::from_value(item).unwrap(),
+            context: Context {
+                id: 0,
+                prev_context: None,
+                option: ContextOption {
+                    reason: ContextReason::Auto,
+                },
+                filetype: None,
+                time: 0,
+                bufnr: 1,
+                cursor_line: line.to_owned(),
+                cursor: Cursor {
+                    row: 1,
+                    col: line.len() as i32 + 1,
+                    line: 0,
+                    character: line.len() as i32,
+                },
+                cursor_before_line: line.to_owned(),
+                cursor_after_line: String::new(),
+            },
+            source_offset,
+            offset: None,
+            word: None,
+            char_class: Arc::new(CharClass::default()),
+        }
+    }
+
+    #[test]
+    fn word_at_end_of_line() {
+        let mut e = entry(
+            json!({
+                "label": "println!",
+                "insertTextFormat": 2,
+                "textEdit": {
+                    "range": {
+                        "start": { "line": 0, "character": 4 },
+                        "end": { "line": 0, "character": 7 },
+                    },
+                    "newText": "println!(\"${1}\")",
+                },
+            }),
+            "    pri",
+            5,
+        );
+        assert_eq!(e.get_word().unwrap(), "println!");
+        assert_eq!(e.get_offset().unwrap(), 5);
+    }
+
+    #[test]
+    fn item_defaults_at_end_of_line() {
+        let range = |start, end| {
+            json!({
+                "start": { "line": 0, "character": start },
+                "end": { "line": 0, "character": end },
+            })
+        };
+        let defaults: CompletionItemDefaults = serde_json::from_value(json!({
+            "editRange": { "insert": range(4, 7), "replace": range(4, 7) },
+            "insertTextFormat": 2,
+        }))
+        .unwrap();
+        let mut e = entry(
+            json!({ "label": "println!", "textEditText": "println!(\"${1}\")" }),
+            "    pri",
+            5,
+        );
+        defaults.apply(&mut e.completion_item);
+        assert_eq!(e.get_word().unwrap(), "println!");
+        assert_eq!(e.get_offset().unwrap(), 5);
+    }
 }
@@ -4,101 +4,225 @@ mod context;
 mod entry;
 mod log;
 mod models;
+mod snippet;
 mod source;
 mod utils;
-use crate::utils::matcher;
+use crate::snippet::expand;
+use crate::source::FilterOption;
+use crate::utils::char_class::CharClasses;
+use crate::utils::extended;
+use crate::utils::matcher::{self, Candidate, MatcherConfig, Query};
 use ::log::debug;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
+/// The `matcher` table.
+///
+/// The functions take an optional `opts` table last: `matching` overrides the matching options
+/// set with `configure`, `positions` adds the `RegionPositions` to the regions of `match` and
+/// `match_extended`, and `regions` adds the regions to the results of `match_many`.
 fn create_matcher_table(lua: &Lua) -> LuaResult<LuaTable> {
     let tbl = lua.create_table()?;
     tbl.set(
         "match",
         lua.create_function(
-            |lua, (input, word, words): (LuaString, LuaString, LuaTable)| {
-                let arg_input = input.as_bytes();
-                let arg_word = word.as_bytes();
+            |lua, (input, word, words, opts): (LuaString, LuaString, LuaTable, LuaValue)| {
+                let arg_input = input.to_str()?;
+                let arg_word = word.to_str()?;
                 let mut arg_words_val = Vec::new();
                 for pair in words.pairs::<LuaValue, LuaString>() {
                     let (_, v) = pair?;
                     arg_words_val.push(v);
                 }
-                let arg_words: Vec<&[u8]> = arg_words_val.iter().map(|i| i.as_bytes()).collect();
-                let matched = matcher::do_match(arg_input, arg_word, arg_words.as_ref());
-                let r = lua.create_table()?;
-                r.set(1, matched.0)?;
-                let matches = lua.create_table()?;
-                let mut count = 1;
-                for m in matched.1 {
-                    let m_lua = lua.create_table()?;
-                    m_lua.set("input_match_start", m.input_match_start + 1)?;
-                    m_lua.set("input_match_end", m.input_match_end)?;
-                    m_lua.set("word_match_start", m.word_match_start + 1)?;
-                    m_lua.set("word_match_end", m.word_match_end)?;
-                    m_lua.set("index", m.index + 1)?;
-                    m_lua.set("strict_ratio", m.strict_ratio)?;
-                    m_lua.set("fuzzy", m.fuzzy)?;
-                    matches.set(count, m_lua)?;
-                    count += 1;
-                }
-                r.set(2, matches)?;
-                Ok(r)
+                let arg_words = arg_words_val
+                    .iter()
+                    .map(|i| i.to_str())
+                    .collect::<LuaResult<Vec<&str>>>()?;
+                let config = MatcherConfig::from_app_data(lua).with_opts(lua, &opts)?;
+                let matched = matcher::do_match(arg_input, arg_word, arg_words.as_ref(), &config);
+                let positions = if matcher::with_positions(&opts)? {
+                    Some((arg_input, arg_word))
+                } else {
+                    None
+                };
+                matcher::matched_to_lua(lua, &matched, positions)
             },
         )?,
     )?;
 
+    tbl.set(
+        "explain",
+        lua.create_function(
+            |lua, (input, word, words, opts): (LuaString, LuaString, Vec<String>, LuaValue)| {
+                let words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+                let config = MatcherConfig::from_app_data(lua).with_opts(lua, &opts)?;
+                matcher::explain(input.to_str()?, word.to_str()?, &words, &config).to_lua(lua)
+            },
+        )?,
+    )?;
+
+    tbl.set(
+        "match_extended",
+        lua.create_function(
+            |lua, (input, word, words, opts): (LuaString, LuaString, Vec<String>, LuaValue)| {
+                let (input, word) = (input.to_str()?, word.to_str()?);
+                let words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+                let config = MatcherConfig::from_app_data(lua).with_opts(lua, &opts)?;
+                let matched = extended::match_extended(input, word, &words, &config);
+                let positions = if matcher::with_positions(&opts)? {
+                    Some((input, word))
+                } else {
+                    None
+                };
+                matcher::matched_to_lua(lua, &matched, positions)
+            },
+        )?,
+    )?;
+
+    tbl.set(
+        "match_many",
+        lua.create_function(
+            |lua, (input, candidates, opts): (LuaString, Vec<Candidate>, LuaValue)| {
+                let config = MatcherConfig::from_app_data(lua).with_opts(lua, &opts)?;
+                let results = matcher::match_many(input.to_str()?, &candidates, &config);
+                matcher::results_to_lua(lua, &results, matcher::with_regions(&opts)?)
+            },
+        )?,
+    )?;
+
+    tbl.set(
+        "query",
+        lua.create_function(|lua, (input, opts): (LuaString, LuaValue)| {
+            let config = MatcherConfig::from_app_data(lua).with_opts(lua, &opts)?;
+            Ok(Query::new(input.to_str()?, &config))
+        })?,
+    )?;
+
+    tbl.set(
+        "configure",
+        lua.create_function(|lua, opts: LuaValue| MatcherConfig::configure(lua, opts))?,
+    )?;
+
     tbl.set(
         "bench_rs",
         lua.create_function(|lua, ()| {
+            let config = MatcherConfig::default();
             for _ in 0..10000 {
-                matcher::do_match(b"", b"a", &Vec::new());
-                matcher::do_match(b"a", b"a", &Vec::new());
-                matcher::do_match(b"ab", b"a", &Vec::new());
-                matcher::do_match(b"ab", b"ab", &Vec::new());
-                matcher::do_match(b"ab", b"a_b", &Vec::new());
-                matcher::do_match(b"ab", b"a_b_c", &Vec::new());
-                matcher::do_match(b"ac", b"a_b_c", &Vec::new());
+                matcher::do_match("", "a", &[], &config);
+                matcher::do_match("a", "a", &[], &config);
+                matcher::do_match("ab", "a", &[], &config);
+                matcher::do_match("ab", "ab", &[], &config);
+                matcher::do_match("ab", "a_b", &[], &config);
+                matcher::do_match("ab", "a_b_c", &[], &config);
+                matcher::do_match("ac", "a_b_c", &[], &config);
 
-                matcher::do_match(b"bora", b"border-radius", &Vec::new());
-                matcher::do_match(b"woroff", b"word_offset", &Vec::new());
-                matcher::do_match(b"call", b"call", &Vec::new());
-                matcher::do_match(b"call", b"condition_all", &Vec::new());
-                matcher::do_match(b"Buffer", b"Buffer", &Vec::new());
-                matcher::do_match(b"Buffer", b"buffer", &Vec::new());
-                matcher::do_match(b"fmodify", b"fnamemodify", &Vec::new());
-                matcher::do_match(b"candlesingle", b"candle#accept#single", &Vec::new());
-                matcher::do_match(b"conso", b"console", &Vec::new());
-                matcher::do_match(b"conso", b"ConstantSourceNode", &Vec::new());
-                matcher::do_match(b"var_", b"var_dump", &Vec::new());
-                matcher::do_match(b"my_", b"my_awesome_variable", &Vec::new());
-                matcher::do_match(b"my_", b"completion_matching_strategy_list", &Vec::new());
-                matcher::do_match(b"luacon", b"lua_context", &Vec::new());
-                matcher::do_match(b"luacon", b"LuaContext", &Vec::new());
-                matcher::do_match(b"call", b"calc", &Vec::new());
+                matcher::do_match("bora", "border-radius", &[], &config);
+                matcher::do_match("woroff", "word_offset", &[], &config);
+                matcher::do_match("call", "call", &[], &config);
+                matcher::do_match("call", "condition_all", &[], &config);
+                matcher::do_match("Buffer", "Buffer", &[], &config);
+                matcher::do_match("Buffer", "buffer", &[], &config);
+                matcher::do_match("fmodify", "fnamemodify", &[], &config);
+                matcher::do_match("candlesingle", "candle#accept#single", &[], &config);
+                matcher::do_match("conso", "console", &[], &config);
+                matcher::do_match("conso", "ConstantSourceNode", &[], &config);
+                matcher::do_match("var_", "var_dump", &[], &config);
+                matcher::do_match("my_", "my_awesome_variable", &[], &config);
+                matcher::do_match("my_", "completion_matching_strategy_list", &[], &config);
+                matcher::do_match("luacon", "lua_context", &[], &config);
+                matcher::do_match("luacon", "LuaContext", &[], &config);
+                matcher::do_match("call", "calc", &[], &config);
 
-                matcher::do_match(b"vi", b"void#", &Vec::new());
-                matcher::do_match(b"vo", b"void#", &Vec::new());
-                matcher::do_match(b"usela", b"useLayoutEffect", &Vec::new());
-                matcher::do_match(b"usela", b"useDataLayer", &Vec::new());
-                matcher::do_match(b"true", b"v:true", vec![b"true".as_slice()].as_slice());
-                matcher::do_match(b"true", b"true", &Vec::new());
-                matcher::do_match(b"g", b"get", vec![b"get".as_slice()].as_slice());
-                matcher::do_match(b"g", b"dein#get", vec![b"dein#get".as_slice()].as_slice());
-                matcher::do_match(b"2", b"[[2021", &Vec::new());
+                matcher::do_match("vi", "void#", &[], &config);
+                matcher::do_match("vo", "void#", &[], &config);
+                matcher::do_match("usela", "useLayoutEffect", &[], &config);
+                matcher::do_match("usela", "useDataLayer", &[], &config);
+                matcher::do_match("true", "v:true", &["true"], &config);
+                matcher::do_match("true", "true", &[], &config);
+                matcher::do_match("g", "get", &["get"], &config);
+                matcher::do_match("g", "dein#get", &["dein#get"], &config);
+                matcher::do_match("2", "[[2021", &[], &config);
             }
 
-            Ok(())
+            // a miss-heavy corpus, where the char bag prefilter rejects most of the words
+            let corpus = [
+                "border-radius",
+                "word_offset",
+                "condition_all",
+                "fnamemodify",
+                "candle#accept#single",
+                "console",
+                "ConstantSourceNode",
+                "var_dump",
+                "my_awesome_variable",
+                "completion_matching_strategy_list",
+                "lua_context",
+                "LuaContext",
+                "useLayoutEffect",
+                "useDataLayer",
+                "getStaticProps",
+                "HTMLInputElement",
+                "addEventListener",
+                "querySelectorAll",
+                "setTimeout",
+                "JSON.stringify",
+                "Array.prototype.map",
+                "vim.api.nvim_buf_get_lines",
+            ];
+            let inputs = ["qzx", "kwj", "vuln", "xyz", "jq", "zap", "gsp", "usela"];
+            let queries: Vec<Query> = inputs.iter().map(|i| Query::new(i, &config)).collect();
+            let r = lua.create_table()?;
+            let start = Instant::now();
+            for _ in 0..1000 {
+                for query in &queries {
+                    for word in &corpus {
+                        query.do_match(word, &[]);
+                    }
+                }
+            }
+            r.set("miss_heavy", start.elapsed().as_millis())?;
+            let start = Instant::now();
+            for _ in 0..1000 {
+                for query in &queries {
+                    for word in &corpus {
+                        query.do_match_without_prefilter(word, &[]);
+                    }
+                }
+            }
+            r.set("miss_heavy_without_prefilter", start.elapsed().as_millis())?;
+            Ok(r)
         })?,
     )?;
 
     Ok(tbl)
 }
 
+fn create_snippet_table(lua: &Lua) -> LuaResult<LuaTable<'_>> {
+    let tbl = lua.create_table()?;
+    tbl.set(
+        "expand",
+        lua.create_function(
+            |lua, (body, vars): (LuaString, Option<HashMap<String, String>>)| {
+                let vars = vars.unwrap_or_default();
+                expand::expand(body.to_str()?, &vars)
+                    .map_err(|e| e.to_lua_err())?
+                    .to_lua(lua)
+            },
+        )?,
+    )?;
+    Ok(tbl)
+}
+
 #[mlua::lua_module]
 fn libnvim_cmp_native_utils(lua: &Lua) -> LuaResult<LuaTable> {
     let exports = lua.create_table()?;
     exports.set("log", log::make_log_tbl(lua)?)?;
     exports.set("matcher", create_matcher_table(lua)?)?;
+    exports.set("snippet", create_snippet_table(lua)?)?;
+    exports.set(
+        "configure_char_classes",
+        lua.create_function(|lua, classes: LuaValue| CharClasses::configure(lua, classes))?,
+    )?;
     exports.set(
         "timestamp",
         lua.create_function(|_, ()| {
@@ -110,10 +234,26 @@ fn libnvim_cmp_native_utils(lua: &Lua) -> LuaResult<LuaTable> {
     exports.set(
         "get_entries_from_source",
         lua.create_function(
-            |lua, (source, ctx, limit): (LuaTable, LuaValue, i64)| -> LuaResult<LuaTable> {
+            |lua,
+             (source, ctx, limit, matching, filter): (
+                LuaTable,
+                LuaValue,
+                i64,
+                LuaValue,
+                LuaValue,
+            )|
+             -> LuaResult<LuaTable> {
                 let ctx = &Context::from_lua(ctx, lua)?;
                 // debug!("ctx: {:?}", ctx);
-                let r = source::get_entries(lua, &source, ctx, limit);
+                let mut config = MatcherConfig::from_app_data(lua).with_matching(lua, matching)?;
+                let option: FilterOption = match filter {
+                    LuaValue::Nil => FilterOption::default(),
+                    _ => lua.from_value(filter)?,
+                };
+                config.matching.smart_case |= option.smart_case;
+                config.char_class =
+                    (*CharClasses::from_app_data(lua, ctx.filetype.as_deref())).clone();
+                let r = source::get_entries(lua, &source, ctx, limit, &config, &option);
                 r
             },
         )?,
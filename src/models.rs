@@ -1,2 +1,2 @@
-pub mod lsp;
 pub mod common;
+pub mod lsp;
@@ -1,334 +1,558 @@
+use std::convert::TryFrom;
+
 use mlua::prelude::*;
+use mlua::serde::{de, ser};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub enum InsertTextFormat {
-    PlainText,
-    Snippet,
+/// Functions and userdata that plugins put in `data` become `null`, and the tables that contain
+/// themselves are cut, instead of failing the item.
+fn deserialize_options() -> de::Options {
+    de::Options::new()
+        .deny_unsupported_types(false)
+        .deny_recursive_tables(false)
 }
 
-impl<'lua> FromLua<'lua> for InsertTextFormat {
-    fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
-        match lua_value {
-            LuaValue::Integer(i) => match i {
-                1 => Ok(InsertTextFormat::PlainText),
-                2 => Ok(InsertTextFormat::Snippet),
-                _ => Err(LuaError::FromLuaConversionError {
-                    from: "Integer",
-                    to: "InsertTextFormat",
-                    message: Some(format!("Unknown value: {}", i)),
-                }),
-            },
-            LuaValue::Nil => Ok(InsertTextFormat::PlainText),
-            _ => Err(LuaError::FromLuaConversionError {
-                from: lua_value.type_name(),
-                to: "InsertTextFormat",
-                message: None,
-            }),
+/// Unset fields are left out of the table instead of being `vim.NIL`.
+const SERIALIZE_OPTIONS: ser::Options = ser::Options::new().serialize_none_to_null(false);
+
+/// Converts the types from and to Lua tables with their serde implementations.
+macro_rules! impl_lua_serde {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl<'lua> FromLua<'lua> for $ty {
+                fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
+                    lua.from_value_with(lua_value, deserialize_options())
+                }
+            }
+
+            impl<'lua> ToLua<'lua> for $ty {
+                fn to_lua(self, lua: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+                    lua.to_value_with(&self, SERIALIZE_OPTIONS)
+                }
+            }
+        )+
+    };
+}
+
+/// An LSP enum of integers, serialized as its number.
+macro_rules! integer_enum {
+    (pub enum $ty:ident { $($variant:ident = $value:literal),+ $(,)? }) => {
+        #[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+        #[serde(try_from = "u8", into = "u8")]
+        pub enum $ty {
+            $($variant = $value,)+
         }
+
+        impl TryFrom<u8> for $ty {
+            type Error = String;
+
+            fn try_from(value: u8) -> Result<Self, Self::Error> {
+                match value {
+                    $($value => Ok($ty::$variant),)+
+                    _ => Err(format!("Unknown {}: {}", stringify!($ty), value)),
+                }
+            }
+        }
+
+        impl From<$ty> for u8 {
+            fn from(value: $ty) -> Self {
+                value as u8
+            }
+        }
+    };
+}
+
+/// Deserializes an optional field to `None` when its value is invalid, e.g. a value of an enum
+/// that a newer LSP version extended, instead of failing the whole item.
+fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer).ok().flatten())
+}
+
+/// `lenient` for a sequence, which also drops its invalid elements.
+fn lenient_seq<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct Element<T>(Option<T>);
+
+    impl<'de, T: Deserialize<'de>> Deserialize<'de> for Element<T> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Ok(Element(T::deserialize(deserializer).ok()))
+        }
+    }
+
+    let seq: Option<Vec<Element<T>>> = lenient(deserializer)?;
+    Ok(seq.map(|seq| seq.into_iter().filter_map(|e| e.0).collect()))
+}
+
+/// Deserializes an invalid value to the default one.
+fn or_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(T::deserialize(deserializer).unwrap_or_default())
+}
+
+integer_enum! {
+    pub enum InsertTextFormat {
+        PlainText = 1,
+        Snippet = 2,
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub enum InsertTextMode {
-    AsIs,
-    AdjustIndentation,
+integer_enum! {
+    pub enum InsertTextMode {
+        AsIs = 1,
+        AdjustIndentation = 2,
+    }
+}
+
+integer_enum! {
+    pub enum CompletionItemKind {
+        Text = 1,
+        Method = 2,
+        Function = 3,
+        Constructor = 4,
+        Field = 5,
+        Variable = 6,
+        Class = 7,
+        Interface = 8,
+        Module = 9,
+        Property = 10,
+        Unit = 11,
+        Value = 12,
+        Enum = 13,
+        Keyword = 14,
+        Snippet = 15,
+        Color = 16,
+        File = 17,
+        Reference = 18,
+        Folder = 19,
+        EnumMember = 20,
+        Constant = 21,
+        Struct = 22,
+        Event = 23,
+        Operator = 24,
+        TypeParameter = 25,
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+integer_enum! {
+    pub enum CompletionItemTag {
+        Deprecated = 1,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MarkupKind {
+    #[default]
+    PlainText,
+    Markdown,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MarkupContent {
+    /// Plain text for the kinds of newer LSP versions
+    #[serde(deserialize_with = "or_default")]
+    pub kind: MarkupKind,
+    pub value: String,
+}
+
+/// The `documentation` of a completion item, a plain string or a `MarkupContent`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Documentation {
+    String(String),
+    MarkupContent(MarkupContent),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CompletionItemLabelDetails {
+    pub detail: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Command {
+    pub title: String,
+    pub command: String,
+    pub arguments: Option<Vec<serde_json::Value>>,
+}
+
+/// A completion item, with the `word` that cmp may add to it.
+///
+/// Items converted by `from_lua_for_filtering` only have the `label`, `filterText`,
+/// `insertText`, `insertTextFormat`, `textEdit`, `textEditText` and `word`, plus what the item
+/// defaults fill in. The other fields are left at their defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CompletionItem {
     pub label: String,
+    pub label_details: Option<CompletionItemLabelDetails>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub kind: Option<CompletionItemKind>,
+    #[serde(default, deserialize_with = "lenient_seq")]
+    pub tags: Option<Vec<CompletionItemTag>>,
+    pub detail: Option<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub documentation: Option<Documentation>,
+    pub deprecated: Option<bool>,
+    pub preselect: Option<bool>,
+    pub sort_text: Option<String>,
     pub filter_text: Option<String>,
     pub insert_text: Option<String>,
-    pub insert_text_format: InsertTextFormat,
+    #[serde(default, deserialize_with = "lenient")]
+    pub insert_text_format: Option<InsertTextFormat>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub insert_text_mode: Option<InsertTextMode>,
     pub text_edit: Option<TextEdit>,
+
+    /// The new text of the `editRange` of the item defaults, instead of the label
+    pub text_edit_text: Option<String>,
+    pub additional_text_edits: Option<Vec<TextEdit>>,
+    pub commit_characters: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub command: Option<Command>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub data: Option<serde_json::Value>,
     pub word: Option<String>,
 }
 
-impl<'lua> FromLua<'lua> for CompletionItem {
-    fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
-        match lua_value {
-            LuaValue::Table(tbl) => Ok(Self {
-                label: tbl.get("label")?,
-                filter_text: tbl.get("filterText")?,
-                insert_text: tbl.get("insertText")?,
-                insert_text_format: tbl.get("insertTextFormat")?,
-                text_edit: tbl.get("textEdit")?,
-                word: tbl.get("word")?,
-            }),
+/// A field of a Lua table, converted with its serde implementation.
+fn get_field<'lua, T: Deserialize<'lua>>(
+    lua: &'lua Lua,
+    tbl: &LuaTable<'lua>,
+    key: &str,
+) -> LuaResult<T> {
+    lua.from_value_with(tbl.get(key)?, deserialize_options())
+}
 
-            LuaValue::Nil => Err(LuaError::FromLuaConversionError {
-                from: "Nil",
-                to: "CompletionItem",
-                message: None,
-            }),
-            LuaValue::Boolean(_) => Err(LuaError::FromLuaConversionError {
-                from: "Boolean",
-                to: "CompletionItem",
-                message: None,
-            }),
-            LuaValue::LightUserData(_) => Err(LuaError::FromLuaConversionError {
-                from: "LightUserData",
-                to: "CompletionItem",
-                message: None,
-            }),
-            LuaValue::Integer(_) => Err(LuaError::FromLuaConversionError {
-                from: "Integer",
-                to: "CompletionItem",
-                message: None,
-            }),
-            LuaValue::Number(_) => Err(LuaError::FromLuaConversionError {
-                from: "Number",
-                to: "CompletionItem",
-                message: None,
-            }),
-            LuaValue::String(_) => Err(LuaError::FromLuaConversionError {
-                from: "String",
-                to: "CompletionItem",
-                message: None,
-            }),
-            LuaValue::Function(_) => Err(LuaError::FromLuaConversionError {
-                from: "Function",
-                to: "CompletionItem",
-                message: None,
-            }),
-            LuaValue::Thread(_) => Err(LuaError::FromLuaConversionError {
-                from: "Thread",
-                to: "CompletionItem",
-                message: None,
-            }),
-            LuaValue::UserData(_) => Err(LuaError::FromLuaConversionError {
-                from: "UserData",
-                to: "CompletionItem",
-                message: None,
-            }),
-            LuaValue::Error(_) => Err(LuaError::FromLuaConversionError {
-                from: "Error",
-                to: "CompletionItem",
-                message: None,
-            }),
-        }
+impl CompletionItem {
+    /// Converts only the fields that filtering needs, so that the others cost nothing and
+    /// can't fail the item. The others are left at their defaults, so the item must not be
+    /// sent back to Lua.
+    pub fn from_lua_for_filtering<'lua>(tbl: &LuaTable<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
+        Ok(Self {
+            label: get_field(lua, tbl, "label")?,
+            filter_text: get_field(lua, tbl, "filterText")?,
+            insert_text: get_field(lua, tbl, "insertText")?,
+            insert_text_format: get_field(lua, tbl, "insertTextFormat").ok().flatten(),
+            text_edit: get_field(lua, tbl, "textEdit")?,
+            text_edit_text: get_field(lua, tbl, "textEditText")?,
+            word: get_field(lua, tbl, "word")?,
+            ..Default::default()
+        })
+    }
+
+    pub fn is_snippet(&self) -> bool {
+        self.insert_text_format == Some(InsertTextFormat::Snippet)
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct Position {
-    pub line: i32,
-    pub character: i32,
+/// The `editRange` of the item defaults.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EditRange {
+    InsertReplace { insert: Range, replace: Range },
+    Range(Range),
 }
 
-impl<'lua> FromLua<'lua> for Position {
-    fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
-        match lua_value {
-            LuaValue::Table(tbl) => Ok(Self {
-                line: tbl.get("line")?,
-                character: tbl.get("character")?,
-            }),
-            LuaValue::Nil => Err(LuaError::FromLuaConversionError {
-                from: "Nil",
-                to: "Position",
-                message: None,
-            }),
-            LuaValue::Boolean(_) => Err(LuaError::FromLuaConversionError {
-                from: "Boolean",
-                to: "Position",
-                message: None,
-            }),
-            LuaValue::LightUserData(_) => Err(LuaError::FromLuaConversionError {
-                from: "LightUserData",
-                to: "Position",
-                message: None,
-            }),
-            LuaValue::Integer(_) => Err(LuaError::FromLuaConversionError {
-                from: "Integer",
-                to: "Position",
-                message: None,
-            }),
-            LuaValue::Number(_) => Err(LuaError::FromLuaConversionError {
-                from: "Number",
-                to: "Position",
-                message: None,
-            }),
-            LuaValue::String(_) => Err(LuaError::FromLuaConversionError {
-                from: "String",
-                to: "Position",
-                message: None,
-            }),
-            LuaValue::Function(_) => Err(LuaError::FromLuaConversionError {
-                from: "Function",
-                to: "Position",
-                message: None,
-            }),
-            LuaValue::Thread(_) => Err(LuaError::FromLuaConversionError {
-                from: "Thread",
-                to: "Position",
-                message: None,
-            }),
-            LuaValue::UserData(_) => Err(LuaError::FromLuaConversionError {
-                from: "UserData",
-                to: "Position",
-                message: None,
-            }),
-            LuaValue::Error(_) => Err(LuaError::FromLuaConversionError {
-                from: "Error",
-                to: "Position",
-                message: None,
-            }),
+/// The `itemDefaults` of a completion response, for the fields its items leave out.
+///
+/// cmp hands these to every entry it creates from the response, so they are applied per entry
+/// and the response itself is never converted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionItemDefaults {
+    pub commit_characters: Option<Vec<String>>,
+    pub edit_range: Option<EditRange>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub insert_text_format: Option<InsertTextFormat>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub insert_text_mode: Option<InsertTextMode>,
+    pub data: Option<serde_json::Value>,
+}
+
+impl CompletionItemDefaults {
+    /// Fills the fields of the item that it doesn't set.
+    ///
+    /// The `editRange` becomes the `textEdit` of the item with its `textEditText`, or its label.
+    pub fn apply(&self, item: &mut CompletionItem) {
+        if item.commit_characters.is_none() {
+            item.commit_characters = self.commit_characters.clone();
+        }
+        if item.text_edit.is_none() {
+            if let Some(edit_range) = &self.edit_range {
+                let new_text = item
+                    .text_edit_text
+                    .clone()
+                    .unwrap_or_else(|| item.label.clone());
+                item.text_edit = Some(match edit_range {
+                    EditRange::Range(range) => TextEdit {
+                        range: Some(range.clone()),
+                        insert: None,
+                        replace: None,
+                        new_text,
+                    },
+                    EditRange::InsertReplace { insert, replace } => TextEdit {
+                        range: None,
+                        insert: Some(insert.clone()),
+                        replace: Some(replace.clone()),
+                        new_text,
+                    },
+                });
+            }
+        }
+        if item.insert_text_format.is_none() {
+            item.insert_text_format = self.insert_text_format;
+        }
+        if item.insert_text_mode.is_none() {
+            item.insert_text_mode = self.insert_text_mode;
+        }
+        if item.data.is_none() {
+            item.data = self.data.clone();
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub line: i32,
+    pub character: i32,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Range {
     pub start: Position,
     pub end: Position,
 }
 
-impl<'lua> FromLua<'lua> for Range {
-    fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
-        match lua_value {
-            LuaValue::Table(tbl) => Ok(Self {
-                start: Position::from_lua(tbl.get("start")?, lua)?,
-                end: Position::from_lua(tbl.get("start")?, lua)?,
-            }),
-            LuaValue::Nil => Err(LuaError::FromLuaConversionError {
-                from: "Nil",
-                to: "Range",
-                message: None,
-            }),
-            LuaValue::Boolean(_) => Err(LuaError::FromLuaConversionError {
-                from: "Boolean",
-                to: "Range",
-                message: None,
-            }),
-            LuaValue::LightUserData(_) => Err(LuaError::FromLuaConversionError {
-                from: "LightUserData",
-                to: "Range",
-                message: None,
-            }),
-            LuaValue::Integer(_) => Err(LuaError::FromLuaConversionError {
-                from: "Integer",
-                to: "Range",
-                message: None,
-            }),
-            LuaValue::Number(_) => Err(LuaError::FromLuaConversionError {
-                from: "Number",
-                to: "Range",
-                message: None,
-            }),
-            LuaValue::String(_) => Err(LuaError::FromLuaConversionError {
-                from: "String",
-                to: "Range",
-                message: None,
-            }),
-            LuaValue::Function(_) => Err(LuaError::FromLuaConversionError {
-                from: "Function",
-                to: "Range",
-                message: None,
-            }),
-            LuaValue::Thread(_) => Err(LuaError::FromLuaConversionError {
-                from: "Thread",
-                to: "Range",
-                message: None,
-            }),
-            LuaValue::UserData(_) => Err(LuaError::FromLuaConversionError {
-                from: "UserData",
-                to: "Range",
-                message: None,
-            }),
-            LuaValue::Error(_) => Err(LuaError::FromLuaConversionError {
-                from: "Error",
-                to: "Range",
-                message: None,
-            }),
-        }
-    }
-}
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TextEdit {
     pub range: Option<Range>,
     pub insert: Option<Range>,  // for InsertReplace
     pub replace: Option<Range>, // for InsertReplace
     pub new_text: String,
-    // pub new_text: Vec<u8>,
 }
 
-impl<'lua> FromLua<'lua> for TextEdit {
-    fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
-        match lua_value {
-            LuaValue::Table(tbl) => Ok(Self {
-                range: {
-                    let v: LuaValue = tbl.get("range")?;
-                    match v {
-                        LuaValue::Nil => None,
-                        _ => Some(Range::from_lua(v, lua)?),
-                    }
+impl_lua_serde!(
+    Documentation,
+    Command,
+    CompletionItem,
+    EditRange,
+    CompletionItemDefaults,
+    Position,
+    Range,
+    TextEdit,
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn deserialize() {
+        let item: CompletionItem = serde_json::from_value(json!({
+            "label": "foo",
+            "kind": 3,
+            "tags": [1],
+            "documentation": { "kind": "markdown", "value": "*foo*" },
+            "insertTextFormat": 2,
+            "textEdit": {
+                "range": {
+                    "start": { "line": 1, "character": 2 },
+                    "end": { "line": 1, "character": 5 },
                 },
-                insert: {
-                    let v: LuaValue = tbl.get("insert")?;
-                    match v {
-                        LuaValue::Nil => None,
-                        _ => Some(Range::from_lua(v, lua)?),
-                    }
+                "newText": "foo($1)",
+            },
+            "data": { "id": 42 },
+            "unknownField": true,
+        }))
+        .unwrap();
+        assert_eq!(item.kind, Some(CompletionItemKind::Function));
+        assert_eq!(item.tags, Some(vec![CompletionItemTag::Deprecated]));
+        assert_eq!(
+            item.documentation,
+            Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: "*foo*".to_owned(),
+            }))
+        );
+        assert!(item.is_snippet());
+        let range = item.text_edit.as_ref().unwrap().range.as_ref().unwrap();
+        assert_eq!(range.end.character, 5);
+        assert_eq!(item.data, Some(json!({ "id": 42 })));
+
+        for value in 1..=25 {
+            assert_eq!(
+                u8::from(CompletionItemKind::try_from(value).unwrap()),
+                value
+            );
+        }
+        assert!(CompletionItemKind::try_from(26).is_err());
+
+        // values of newer LSP versions are dropped, not the item
+        let item: CompletionItem = serde_json::from_value(json!({
+            "label": "a",
+            "kind": 26,
+            "tags": [2, 1, "x"],
+            "documentation": { "kind": "html", "value": "<b>a</b>" },
+            "insertTextFormat": 3,
+            "insertTextMode": -1,
+        }))
+        .unwrap();
+        assert_eq!(item.kind, None);
+        assert_eq!(item.tags, Some(vec![CompletionItemTag::Deprecated]));
+        assert!(matches!(
+            item.documentation,
+            Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::PlainText,
+                ..
+            }))
+        ));
+        assert_eq!(item.insert_text_format, None);
+        assert_eq!(item.insert_text_mode, None);
+
+        // so are the opaque fields that don't convert
+        let item: CompletionItem = serde_json::from_value(json!({
+            "label": "a",
+            "documentation": { "value": 1 },
+            "command": { "title": "t" },
+        }))
+        .unwrap();
+        assert_eq!(item.documentation, None);
+        assert_eq!(item.command, None);
+
+        let defaults: CompletionItemDefaults = serde_json::from_value(json!({
+            "editRange": {
+                "insert": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 1 },
                 },
-                replace: {
-                    let v: LuaValue = tbl.get("replace")?;
-                    match v {
-                        LuaValue::Nil => None,
-                        _ => Some(Range::from_lua(v, lua)?),
-                    }
+                "replace": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 3 },
                 },
-                new_text: tbl.get::<_, String>("newText")?,
-            }),
-            LuaValue::Nil => Err(LuaError::FromLuaConversionError {
-                from: "Nil",
-                to: "TextEdit",
-                message: None,
-            }),
-            LuaValue::Boolean(_) => Err(LuaError::FromLuaConversionError {
-                from: "Boolean",
-                to: "TextEdit",
-                message: None,
-            }),
-            LuaValue::LightUserData(_) => Err(LuaError::FromLuaConversionError {
-                from: "LightUserData",
-                to: "TextEdit",
-                message: None,
-            }),
-            LuaValue::Integer(_) => Err(LuaError::FromLuaConversionError {
-                from: "Integer",
-                to: "TextEdit",
-                message: None,
-            }),
-            LuaValue::Number(_) => Err(LuaError::FromLuaConversionError {
-                from: "Number",
-                to: "TextEdit",
-                message: None,
-            }),
-            LuaValue::String(_) => Err(LuaError::FromLuaConversionError {
-                from: "String",
-                to: "TextEdit",
-                message: None,
-            }),
-            LuaValue::Function(_) => Err(LuaError::FromLuaConversionError {
-                from: "Function",
-                to: "TextEdit",
-                message: None,
-            }),
-            LuaValue::Thread(_) => Err(LuaError::FromLuaConversionError {
-                from: "Thread",
-                to: "TextEdit",
-                message: None,
-            }),
-            LuaValue::UserData(_) => Err(LuaError::FromLuaConversionError {
-                from: "UserData",
-                to: "TextEdit",
-                message: None,
-            }),
-            LuaValue::Error(_) => Err(LuaError::FromLuaConversionError {
-                from: "Error",
-                to: "TextEdit",
-                message: None,
-            }),
+            },
+        }))
+        .unwrap();
+        assert!(matches!(
+            defaults.edit_range,
+            Some(EditRange::InsertReplace { .. })
+        ));
+    }
+
+    fn range(start: i32, end: i32) -> Range {
+        Range {
+            start: Position {
+                line: 0,
+                character: start,
+            },
+            end: Position {
+                line: 0,
+                character: end,
+            },
         }
     }
+
+    #[test]
+    fn apply_defaults() {
+        let defaults = CompletionItemDefaults {
+            commit_characters: Some(vec![".".to_owned()]),
+            edit_range: Some(EditRange::Range(range(0, 2))),
+            insert_text_format: Some(InsertTextFormat::Snippet),
+            insert_text_mode: Some(InsertTextMode::AsIs),
+            data: Some(json!(1)),
+        };
+        let mut item: CompletionItem = serde_json::from_value(json!({ "label": "foo" })).unwrap();
+        defaults.apply(&mut item);
+        assert_eq!(item.commit_characters, Some(vec![".".to_owned()]));
+        assert_eq!(
+            item.text_edit,
+            Some(TextEdit {
+                range: Some(range(0, 2)),
+                insert: None,
+                replace: None,
+                new_text: "foo".to_owned(),
+            })
+        );
+        assert!(item.is_snippet());
+        assert_eq!(item.insert_text_mode, Some(InsertTextMode::AsIs));
+        assert_eq!(item.data, Some(json!(1)));
+
+        // the fields of the item win
+        let mut item: CompletionItem = serde_json::from_value(json!({
+            "label": "bar",
+            "commitCharacters": [],
+            "insertTextFormat": 1,
+            "textEdit": {
+                "range": {
+                    "start": { "line": 0, "character": 1 },
+                    "end": { "line": 0, "character": 1 },
+                },
+                "newText": "baz",
+            },
+            "data": 2,
+        }))
+        .unwrap();
+        defaults.apply(&mut item);
+        assert_eq!(item.commit_characters, Some(vec![]));
+        assert_eq!(item.text_edit.as_ref().unwrap().new_text, "baz");
+        assert!(!item.is_snippet());
+        assert_eq!(item.data, Some(json!(2)));
+
+        // an insert and replace range, with the text edit text instead of the label
+        let defaults = CompletionItemDefaults {
+            commit_characters: None,
+            edit_range: Some(EditRange::InsertReplace {
+                insert: range(0, 1),
+                replace: range(0, 3),
+            }),
+            insert_text_format: None,
+            insert_text_mode: None,
+            data: None,
+        };
+        let mut item: CompletionItem =
+            serde_json::from_value(json!({ "label": "foo", "textEditText": "foo()" })).unwrap();
+        defaults.apply(&mut item);
+        assert_eq!(
+            item.text_edit,
+            Some(TextEdit {
+                range: None,
+                insert: Some(range(0, 1)),
+                replace: Some(range(0, 3)),
+                new_text: "foo()".to_owned(),
+            })
+        );
+        assert_eq!(item.insert_text_format, None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let item: CompletionItem = serde_json::from_value(json!({
+            "label": "bar",
+            "labelDetails": { "detail": "(x)" },
+            "documentation": "plain",
+            "insertTextMode": 2,
+            "commitCharacters": ["."],
+            "command": { "title": "t", "command": "c", "arguments": [1, "a"] },
+        }))
+        .unwrap();
+        let value = serde_json::to_value(&item).unwrap();
+        assert_eq!(value["insertTextMode"], json!(2));
+        assert_eq!(value["labelDetails"]["detail"], json!("(x)"));
+        assert_eq!(
+            serde_json::from_value::<CompletionItem>(value).unwrap(),
+            item
+        );
+    }
 }
@@ -0,0 +1,464 @@
+//! Expansion of a parsed snippet into its final text and tabstop ranges.
+//!
+//! Variables come from Lua (`TM_FILENAME`, `TM_SELECTED_TEXT`, ...), except the `CURRENT_*`
+//! date variables which are computed in local time unless given. Following the LSP
+//! specification, a known variable without a value is empty (or its default) and an unknown one
+//! is its name.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset, Local, TimeZone};
+use fancy_regex::{Captures, Regex};
+use mlua::prelude::*;
+
+use crate::snippet::parser::{self, FormatItem, Modifier, Node, Transform};
+
+const KNOWN_VARIABLES: &[&str] = &[
+    "TM_SELECTED_TEXT",
+    "TM_CURRENT_LINE",
+    "TM_CURRENT_WORD",
+    "TM_LINE_INDEX",
+    "TM_LINE_NUMBER",
+    "TM_FILENAME",
+    "TM_FILENAME_BASE",
+    "TM_DIRECTORY",
+    "TM_FILEPATH",
+    "RELATIVE_FILEPATH",
+    "CLIPBOARD",
+    "WORKSPACE_NAME",
+    "WORKSPACE_FOLDER",
+    "CURSOR_INDEX",
+    "CURSOR_NUMBER",
+    "BLOCK_COMMENT_START",
+    "BLOCK_COMMENT_END",
+    "LINE_COMMENT",
+];
+
+/// A tabstop, placeholder or choice of the expanded text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tabstop {
+    pub index: usize,
+
+    /// Byte offsets in the expanded text, the end is exclusive
+    pub start: usize,
+    pub end: usize,
+
+    pub choices: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub text: String,
+
+    /// Sorted by index with `$0` last, and by position for the mirrors of the same index
+    pub tabstops: Vec<Tabstop>,
+}
+
+impl Expansion {
+    /// `{ text = ..., tabstops = { { index = 1, start = { row, col }, ["end"] = { row, col },
+    /// choices = { ... } }, ... } }`, the positions are 0-based rows and byte columns relative to
+    /// the start of the text, as `nvim_buf_set_extmark` takes them.
+    pub fn to_lua<'lua>(&self, lua: &'lua Lua) -> LuaResult<LuaTable<'lua>> {
+        let tbl = lua.create_table()?;
+        tbl.set("text", self.text.as_str())?;
+        let tabstops = lua.create_table()?;
+        for (i, t) in self.tabstops.iter().enumerate() {
+            let t_lua = lua.create_table()?;
+            t_lua.set("index", t.index)?;
+            t_lua.set("start", self.position(lua, t.start)?)?;
+            t_lua.set("end", self.position(lua, t.end)?)?;
+            if let Some(choices) = &t.choices {
+                t_lua.set("choices", choices.clone())?;
+            }
+            tabstops.set(i + 1, t_lua)?;
+        }
+        tbl.set("tabstops", tabstops)?;
+        Ok(tbl)
+    }
+
+    fn position<'lua>(&self, lua: &'lua Lua, offset: usize) -> LuaResult<LuaTable<'lua>> {
+        let before = &self.text[..offset];
+        let row = before.matches('\n').count();
+        let col = offset - before.rfind('\n').map_or(0, |i| i + 1);
+        lua.create_sequence_from([row, col])
+    }
+}
+
+/// Expands the snippet with the variables and the current local time.
+pub fn expand(body: &str, vars: &HashMap<String, String>) -> Result<Expansion, fancy_regex::Error> {
+    let now = Local::now();
+    let unix = u64::try_from(now.timestamp()).unwrap_or(0);
+    expand_at(body, vars, unix, now.offset().local_minus_utc())
+}
+
+/// `expand` at the given unix time, in a time zone `utc_offset` seconds east of UTC.
+pub fn expand_at(
+    body: &str,
+    vars: &HashMap<String, String>,
+    now: u64,
+    utc_offset: i32,
+) -> Result<Expansion, fancy_regex::Error> {
+    let snippet = parser::parse(body);
+    let offset = FixedOffset::east_opt(utc_offset).unwrap_or_else(|| FixedOffset::east(0));
+    let mut expander = Expander {
+        vars,
+        now: offset.timestamp(now as i64, 0),
+        values: HashMap::new(),
+    };
+    // the first pass finds the values of the tabstops, which mirrors may use before their
+    // placeholder
+    expander.render(&snippet.nodes, &mut String::new(), &mut Vec::new())?;
+    let mut text = String::new();
+    let mut tabstops = Vec::new();
+    expander.render(&snippet.nodes, &mut text, &mut tabstops)?;
+    if !tabstops.iter().any(|t| t.index == 0) {
+        tabstops.push(Tabstop {
+            index: 0,
+            start: text.len(),
+            end: text.len(),
+            choices: None,
+        });
+    }
+    tabstops.sort_by_key(|t| (t.index == 0, t.index, t.start));
+    Ok(Expansion { text, tabstops })
+}
+
+struct Expander<'a> {
+    vars: &'a HashMap<String, String>,
+    now: DateTime<FixedOffset>,
+
+    /// The text of the first placeholder or choice of every tabstop index
+    values: HashMap<usize, String>,
+}
+
+impl Expander<'_> {
+    fn render(
+        &mut self,
+        nodes: &[Node],
+        out: &mut String,
+        tabstops: &mut Vec<Tabstop>,
+    ) -> Result<(), fancy_regex::Error> {
+        for node in nodes {
+            let start = out.len();
+            let mut choices = None;
+            let index = match node {
+                Node::Text(text) => {
+                    out.push_str(text);
+                    continue;
+                }
+                Node::Tabstop { index, transform } => {
+                    let value = self.values.get(index).cloned().unwrap_or_default();
+                    match transform {
+                        Some(transform) => out.push_str(&apply_transform(&value, transform)?),
+                        None => out.push_str(&value),
+                    }
+                    *index
+                }
+                Node::Placeholder { index, children } => {
+                    self.render(children, out, tabstops)?;
+                    self.values
+                        .entry(*index)
+                        .or_insert_with(|| out[start..].to_owned());
+                    *index
+                }
+                Node::Choice { index, options } => {
+                    let first = options.first().cloned().unwrap_or_default();
+                    out.push_str(&first);
+                    self.values.entry(*index).or_insert(first);
+                    choices = Some(options.clone());
+                    *index
+                }
+                Node::Variable {
+                    name,
+                    default,
+                    transform,
+                } => {
+                    match (self.resolve(name), default) {
+                        (Some(value), _) if !value.is_empty() => match transform {
+                            Some(transform) => out.push_str(&apply_transform(&value, transform)?),
+                            None => out.push_str(&value),
+                        },
+                        (_, Some(default)) => self.render(default, out, tabstops)?,
+                        (Some(_), None) => {}
+                        (None, None) => out.push_str(name),
+                    }
+                    continue;
+                }
+            };
+            tabstops.push(Tabstop {
+                index,
+                start,
+                end: out.len(),
+                choices,
+            });
+        }
+        Ok(())
+    }
+
+    /// The value of a variable, `None` if it is unknown.
+    fn resolve(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.vars.get(name) {
+            return Some(value.clone());
+        }
+        if name == "TM_FILENAME_BASE" {
+            if let Some(filename) = self.vars.get("TM_FILENAME") {
+                let base = match filename.rfind('.') {
+                    Some(i) if i > 0 => &filename[..i],
+                    _ => filename,
+                };
+                return Some(base.to_owned());
+            }
+        }
+        if let Some(value) = self.date_variable(name) {
+            return Some(value);
+        }
+        if KNOWN_VARIABLES.contains(&name) {
+            Some(String::new())
+        } else {
+            None
+        }
+    }
+
+    fn date_variable(&self, name: &str) -> Option<String> {
+        let format = match name {
+            "CURRENT_YEAR" => "%Y",
+            "CURRENT_YEAR_SHORT" => "%y",
+            "CURRENT_MONTH" => "%m",
+            "CURRENT_MONTH_NAME" => "%B",
+            "CURRENT_MONTH_NAME_SHORT" => "%b",
+            "CURRENT_DATE" => "%d",
+            "CURRENT_DAY_NAME" => "%A",
+            "CURRENT_DAY_NAME_SHORT" => "%a",
+            "CURRENT_HOUR" => "%H",
+            "CURRENT_MINUTE" => "%M",
+            "CURRENT_SECOND" => "%S",
+            "CURRENT_SECONDS_UNIX" => "%s",
+            _ => return None,
+        };
+        Some(self.now.format(format).to_string())
+    }
+}
+
+/// Replaces the first match of the regex in the value, or all of them with the `g` option.
+fn apply_transform(value: &str, transform: &Transform) -> Result<String, fancy_regex::Error> {
+    let mut flags = String::new();
+    for c in transform.options.chars() {
+        if matches!(c, 'i' | 'm' | 's') {
+            flags.push(c);
+        }
+    }
+    let pattern = if flags.is_empty() {
+        transform.regex.clone()
+    } else {
+        format!("(?{}){}", flags, transform.regex)
+    };
+    let regex = Regex::new(&pattern)?;
+    let global = transform.options.contains('g');
+
+    let mut result = String::new();
+    let mut last = 0;
+    for captures in regex.captures_iter(value) {
+        let captures = captures?;
+        let m = captures.get(0).unwrap();
+        result.push_str(&value[last..m.start()]);
+        result.push_str(&format_captures(&captures, &transform.format));
+        last = m.end();
+        if !global {
+            break;
+        }
+    }
+    result.push_str(&value[last..]);
+    Ok(result)
+}
+
+fn format_captures(captures: &Captures, format: &[FormatItem]) -> String {
+    let group = |i: usize| captures.get(i).map_or("", |m| m.as_str());
+    let mut text = String::new();
+    for item in format {
+        match item {
+            FormatItem::Text(t) => text.push_str(t),
+            FormatItem::Group(i) => text.push_str(group(*i)),
+            FormatItem::Modifier(i, modifier) => {
+                text.push_str(&apply_modifier(group(*i), *modifier))
+            }
+            FormatItem::Conditional {
+                group: i,
+                if_text,
+                else_text,
+            } => {
+                let branch = if group(*i).is_empty() {
+                    else_text
+                } else {
+                    if_text
+                };
+                if let Some(branch) = branch {
+                    text.push_str(branch);
+                }
+            }
+        }
+    }
+    text
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn apply_modifier(value: &str, modifier: Modifier) -> String {
+    match modifier {
+        Modifier::Upcase => value.to_uppercase(),
+        Modifier::Downcase => value.to_lowercase(),
+        Modifier::Capitalize => capitalize(value),
+        Modifier::Camelcase | Modifier::Pascalcase => {
+            let words = value
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|w| !w.is_empty());
+            let mut text = String::new();
+            for (i, word) in words.enumerate() {
+                if i == 0 && modifier == Modifier::Camelcase {
+                    text.push_str(&word.to_lowercase());
+                } else {
+                    text.push_str(&capitalize(&word.to_lowercase()));
+                }
+            }
+            text
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 2024-02-29T13:05:09Z, a Thursday
+    const NOW: u64 = 1709211909;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn ranges(e: &Expansion) -> Vec<(usize, &str)> {
+        e.tabstops
+            .iter()
+            .map(|t| (t.index, &e.text[t.start..t.end]))
+            .collect()
+    }
+
+    #[test]
+    fn dates() {
+        let e = expand_at(
+            "$CURRENT_YEAR-$CURRENT_MONTH-$CURRENT_DATE $CURRENT_HOUR:$CURRENT_MINUTE:$CURRENT_SECOND \
+             $CURRENT_DAY_NAME_SHORT $CURRENT_MONTH_NAME $CURRENT_YEAR_SHORT",
+            &HashMap::new(),
+            NOW,
+            0,
+        )
+        .unwrap();
+        assert_eq!(e.text, "2024-02-29 13:05:09 Thu February 24");
+        // given values win
+        let e = expand_at("$CURRENT_YEAR", &vars(&[("CURRENT_YEAR", "1999")]), NOW, 0).unwrap();
+        assert_eq!(e.text, "1999");
+        // in local time, which can be on another day than in UTC
+        let body = "$CURRENT_DATE $CURRENT_HOUR $CURRENT_DAY_NAME $CURRENT_SECONDS_UNIX";
+        let e = expand_at(body, &HashMap::new(), NOW, 9 * 3600).unwrap();
+        assert_eq!(e.text, "29 22 Thursday 1709211909");
+        let e = expand_at(body, &HashMap::new(), NOW, -14 * 3600).unwrap();
+        assert_eq!(e.text, "28 23 Wednesday 1709211909");
+        let e = expand_at(
+            "$CURRENT_MONTH_NAME_SHORT $CURRENT_DAY_NAME_SHORT",
+            &HashMap::new(),
+            0,
+            0,
+        );
+        assert_eq!(e.unwrap().text, "Jan Thu");
+    }
+
+    #[test]
+    fn variables() {
+        let v = vars(&[("TM_FILENAME", "matcher.rs"), ("TM_SELECTED_TEXT", "x + 1")]);
+        let e = expand_at(
+            "// $TM_FILENAME_BASE\nlet v = ${TM_SELECTED_TEXT:${1:default}};",
+            &v,
+            NOW,
+            0,
+        )
+        .unwrap();
+        assert_eq!(e.text, "// matcher\nlet v = x + 1;");
+        assert_eq!(ranges(&e), vec![(0, "")]);
+
+        let e = expand_at(
+            "${TM_SELECTED_TEXT:${1:default}} $CLIPBOARD|$UNKNOWN",
+            &v,
+            NOW,
+            0,
+        );
+        let e = e.unwrap();
+        assert_eq!(e.text, "x + 1 |UNKNOWN");
+
+        let e = expand_at("${TM_CURRENT_WORD:${1:default}}", &v, NOW, 0).unwrap();
+        assert_eq!(e.text, "default");
+        assert_eq!(ranges(&e), vec![(1, "default"), (0, "")]);
+    }
+
+    #[test]
+    fn tabstops() {
+        let e = expand_at(
+            "fn ${1:name}(${2:arg}: ${3|u32,i64|}) {\n\t$0\n}\n// $1",
+            &HashMap::new(),
+            NOW,
+            0,
+        )
+        .unwrap();
+        assert_eq!(e.text, "fn name(arg: u32) {\n\t\n}\n// name");
+        assert_eq!(
+            ranges(&e),
+            vec![(1, "name"), (1, "name"), (2, "arg"), (3, "u32"), (0, "")]
+        );
+        assert_eq!(
+            e.tabstops[3].choices,
+            Some(vec!["u32".into(), "i64".into()])
+        );
+        assert_eq!((e.tabstops[4].start, e.tabstops[4].end), (21, 21));
+
+        // mirrors before their placeholder, nested placeholders
+        let e = expand_at("$1 ${1:a ${2:b}}", &HashMap::new(), NOW, 0).unwrap();
+        assert_eq!(e.text, "a b a b");
+        assert_eq!(ranges(&e), vec![(1, "a b"), (1, "a b"), (2, "b"), (0, "")]);
+    }
+
+    #[test]
+    fn transforms() {
+        let v = vars(&[("TM_FILENAME", "my-file_name.test.ts")]);
+        let e = |body: &str| expand_at(body, &v, NOW, 0).unwrap().text;
+        assert_eq!(e("${TM_FILENAME/(.*)\\..+$/$1/}"), "my-file_name.test");
+        assert_eq!(e("${TM_FILENAME/[-_.]/ /}"), "my file_name.test.ts");
+        assert_eq!(e("${TM_FILENAME/[-_.]/ /g}"), "my file name test ts");
+        assert_eq!(
+            e("${TM_FILENAME/^([^.]+).*/${1:/pascalcase}/}"),
+            "MyFileName"
+        );
+        assert_eq!(
+            e("${TM_FILENAME/^([^.]+).*/${1:/camelcase}/}"),
+            "myFileName"
+        );
+        assert_eq!(e("${TM_FILENAME/^(\\w+).*/${1:/upcase}/}"), "MY");
+        assert_eq!(e("${TM_FILENAME/^(\\w+).*/${1:/capitalize}/}"), "My");
+        assert_eq!(
+            e("${TM_FILENAME/MY/${0:/downcase}/i}"),
+            "my-file_name.test.ts"
+        );
+        assert_eq!(
+            e("${TM_FILENAME/(test)|(spec)/${1:?T:S}${2:+!}/g}"),
+            "my-file_name.T.ts"
+        );
+        assert_eq!(e("${1:foo} ${1/(o+)/${1:/upcase}/}"), "foo fOO");
+        assert!(expand_at("${TM_FILENAME/(/x/}", &v, NOW, 0).is_err());
+    }
+}
@@ -0,0 +1,791 @@
+//! The [LSP snippet syntax](https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#snippet_syntax).
+//!
+//! ```text
+//! any         ::= tabstop | placeholder | choice | variable | text
+//! tabstop     ::= '$' int | '${' int '}' | '${' int transform '}'
+//! placeholder ::= '${' int ':' any '}'
+//! choice      ::= '${' int '|' text (',' text)* '|}'
+//! variable    ::= '$' var | '${' var '}' | '${' var ':' any '}' | '${' var transform '}'
+//! transform   ::= '/' regex '/' (format | text)+ '/' options
+//! format      ::= '$' int | '${' int '}' | '${' int ':' '/upcase' | '/downcase' | '/capitalize'
+//!                 | '/camelcase' | '/pascalcase' '}' | '${' int ':+' if '}'
+//!                 | '${' int ':?' if ':' else '}' | '${' int ':-' else '}' | '${' int ':' else '}'
+//! ```
+//!
+//! Like VS Code, anything that doesn't parse is kept as text, so parsing never fails: `${1:foo`
+//! is the text `${1:foo`.
+
+use std::collections::HashMap;
+
+/// A parsed snippet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Text(String),
+    Tabstop {
+        index: usize,
+        transform: Option<Transform>,
+    },
+    Placeholder {
+        index: usize,
+        children: Vec<Node>,
+    },
+    Choice {
+        index: usize,
+        options: Vec<String>,
+    },
+    Variable {
+        name: String,
+        default: Option<Vec<Node>>,
+        transform: Option<Transform>,
+    },
+}
+
+/// `/regex/format/options`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transform {
+    pub regex: String,
+    pub format: Vec<FormatItem>,
+    pub options: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatItem {
+    Text(String),
+
+    /// `$1` or `${1}`
+    Group(usize),
+
+    /// `${1:/upcase}`
+    Modifier(usize, Modifier),
+
+    /// `${1:+if}`, `${1:?if:else}`, `${1:-else}` or `${1:else}`
+    Conditional {
+        group: usize,
+        if_text: Option<String>,
+        else_text: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    Upcase,
+    Downcase,
+    Capitalize,
+    Camelcase,
+    Pascalcase,
+}
+
+impl Snippet {
+    /// The text of the snippet without its markers: placeholders are their text, choices their
+    /// first option, and variables their default or nothing.
+    pub fn to_plain_text(&self) -> String {
+        let mut text = String::new();
+        write_plain_text(&self.nodes, &mut text);
+        text
+    }
+}
+
+fn write_plain_text(nodes: &[Node], text: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(t) => text.push_str(t),
+            Node::Tabstop { .. } => {}
+            Node::Placeholder { children, .. } => write_plain_text(children, text),
+            Node::Choice { options, .. } => {
+                if let Some(first) = options.first() {
+                    text.push_str(first);
+                }
+            }
+            Node::Variable { default, .. } => {
+                if let Some(default) = default {
+                    write_plain_text(default, text);
+                }
+            }
+        }
+    }
+}
+
+/// Placeholders and variable defaults nested deeper than this are text, so that parsing can't
+/// overflow the stack.
+const MAX_DEPTH: usize = 100;
+
+pub fn parse(text: &str) -> Snippet {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+        depth: 0,
+        unclosed: None,
+        unterminated: HashMap::new(),
+    };
+    Snippet {
+        nodes: parser.parse_any(false),
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+
+    /// Number of placeholders and variable defaults around the position
+    depth: usize,
+
+    /// Set when `parse_dollar` fails on a `${1:` or `${VAR:` without its `}`, see `unclosed`
+    unclosed: Option<Vec<Node>>,
+
+    /// The first position from which a `Scan` reached the end of the text, see `scan`
+    unterminated: HashMap<Scan, usize>,
+}
+
+/// The parts of the text that the parser scans up to a closing char.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Scan {
+    Choice,
+    Regex,
+    FormatText(char),
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Nodes up to the end of the text, or up to an unescaped `}` in a placeholder.
+    fn parse_any(&mut self, nested: bool) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '}' if nested => break,
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(e @ ('$' | '}' | '\\')) => {
+                            self.pos += 1;
+                            text.push(e);
+                        }
+                        _ => text.push('\\'),
+                    }
+                }
+                '$' => {
+                    let start = self.pos;
+                    match self.parse_dollar() {
+                        Some(node) => {
+                            if !text.is_empty() {
+                                nodes.push(Node::Text(std::mem::take(&mut text)));
+                            }
+                            nodes.push(node);
+                        }
+                        None => match self.unclosed.take() {
+                            Some(unclosed) => {
+                                for node in unclosed {
+                                    match node {
+                                        Node::Text(t) => text.push_str(&t),
+                                        node => {
+                                            if !text.is_empty() {
+                                                nodes.push(Node::Text(std::mem::take(&mut text)));
+                                            }
+                                            nodes.push(node);
+                                        }
+                                    }
+                                }
+                            }
+                            None => {
+                                self.pos = start + 1;
+                                text.push('$');
+                            }
+                        },
+                    }
+                }
+                _ => {
+                    self.pos += 1;
+                    text.push(c);
+                }
+            }
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        nodes
+    }
+
+    /// The nodes of the text from `start` when the placeholder or variable default starting
+    /// there has no `}`.
+    ///
+    /// `parse_any` only stops early at a `}`, so its children ran to the end of the text. Parsing
+    /// the text after the `$` again would give the `${1:` as text followed by the same nodes,
+    /// but every unclosed level would parse the rest of the text again, which takes exponential
+    /// time with nested ones.
+    fn unclosed(&self, start: usize, children_start: usize, children: Vec<Node>) -> Vec<Node> {
+        let text = self.chars[start..children_start].iter().collect();
+        std::iter::once(Node::Text(text)).chain(children).collect()
+    }
+
+    fn parse_int(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return None;
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    fn parse_var(&mut self) -> Option<String> {
+        let start = self.pos;
+        if !self
+            .peek()
+            .is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        {
+            return None;
+        }
+        while self
+            .peek()
+            .is_some_and(|c| c == '_' || c.is_ascii_alphanumeric())
+        {
+            self.pos += 1;
+        }
+        Some(self.chars[start..self.pos].iter().collect())
+    }
+
+    /// Any construct starting with `$`, the position is unspecified when it returns `None`.
+    fn parse_dollar(&mut self) -> Option<Node> {
+        let start = self.pos;
+        self.pos += 1;
+        if let Some(index) = self.parse_int() {
+            return Some(Node::Tabstop {
+                index,
+                transform: None,
+            });
+        }
+        if let Some(name) = self.parse_var() {
+            return Some(Node::Variable {
+                name,
+                default: None,
+                transform: None,
+            });
+        }
+        if !self.eat('{') {
+            return None;
+        }
+        if let Some(index) = self.parse_int() {
+            if self.eat('}') {
+                return Some(Node::Tabstop {
+                    index,
+                    transform: None,
+                });
+            }
+            if self.eat(':') {
+                let children_start = self.pos;
+                let children = self.parse_nested()?;
+                if self.eat('}') {
+                    return Some(Node::Placeholder { index, children });
+                }
+                self.unclosed = Some(self.unclosed(start, children_start, children));
+                return None;
+            }
+            if self.eat('|') {
+                let options = self.parse_choice()?;
+                return Some(Node::Choice { index, options });
+            }
+            let transform = self.parse_transform()?;
+            return self.eat('}').then_some(Node::Tabstop {
+                index,
+                transform: Some(transform),
+            });
+        }
+        let name = self.parse_var()?;
+        if self.eat('}') {
+            return Some(Node::Variable {
+                name,
+                default: None,
+                transform: None,
+            });
+        }
+        if self.eat(':') {
+            let default_start = self.pos;
+            let default = self.parse_nested()?;
+            if self.eat('}') {
+                return Some(Node::Variable {
+                    name,
+                    default: Some(default),
+                    transform: None,
+                });
+            }
+            self.unclosed = Some(self.unclosed(start, default_start, default));
+            return None;
+        }
+        let transform = self.parse_transform()?;
+        self.eat('}').then_some(Node::Variable {
+            name,
+            default: None,
+            transform: Some(transform),
+        })
+    }
+
+    /// `parse_any` in a placeholder or a variable default, `None` past `MAX_DEPTH`.
+    fn parse_nested(&mut self) -> Option<Vec<Node>> {
+        if self.depth == MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let nodes = self.parse_any(true);
+        self.depth -= 1;
+        Some(nodes)
+    }
+
+    /// Runs `f`, unless a `scan` from an earlier position reached the end of the text.
+    ///
+    /// The scans start after a char that is not a backslash, so a scan from an earlier position
+    /// went through the same chars the same way and the new one would reach the end too.
+    /// Without this, every unclosed construct would scan the rest of the text again, which
+    /// takes quadratic time.
+    fn scan<T>(&mut self, scan: Scan, f: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        let start = self.pos;
+        if self.unterminated.get(&scan).is_some_and(|p| *p <= start) {
+            return None;
+        }
+        let result = f(self);
+        if result.is_none() && self.pos >= self.chars.len() {
+            self.unterminated.entry(scan).or_insert(start);
+        }
+        result
+    }
+
+    /// The options of a choice after `${1|`, up to and including `|}`.
+    fn parse_choice(&mut self) -> Option<Vec<String>> {
+        self.scan(Scan::Choice, Self::scan_choice)
+    }
+
+    fn scan_choice(&mut self) -> Option<Vec<String>> {
+        let mut options = Vec::new();
+        let mut option = String::new();
+        loop {
+            match self.peek()? {
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(e @ ('$' | '}' | '\\' | ',' | '|')) => {
+                            self.pos += 1;
+                            option.push(e);
+                        }
+                        _ => option.push('\\'),
+                    }
+                }
+                ',' => {
+                    self.pos += 1;
+                    options.push(std::mem::take(&mut option));
+                }
+                '|' => {
+                    self.pos += 1;
+                    if !self.eat('}') {
+                        return None;
+                    }
+                    options.push(option);
+                    return Some(options);
+                }
+                c => {
+                    self.pos += 1;
+                    option.push(c);
+                }
+            }
+        }
+    }
+
+    /// The text up to an unescaped `/`, which is consumed. Escaped slashes lose their backslash,
+    /// other escapes are kept for the regex engine.
+    fn parse_regex(&mut self) -> Option<String> {
+        self.scan(Scan::Regex, Self::scan_regex)
+    }
+
+    fn scan_regex(&mut self) -> Option<String> {
+        let mut regex = String::new();
+        loop {
+            match self.peek()? {
+                '/' => {
+                    self.pos += 1;
+                    return Some(regex);
+                }
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek()? {
+                        '/' => regex.push('/'),
+                        c => {
+                            regex.push('\\');
+                            regex.push(c);
+                        }
+                    }
+                    self.pos += 1;
+                }
+                c => {
+                    self.pos += 1;
+                    regex.push(c);
+                }
+            }
+        }
+    }
+
+    fn parse_transform(&mut self) -> Option<Transform> {
+        if !self.eat('/') {
+            return None;
+        }
+        let regex = self.parse_regex()?;
+        let format = self.parse_format()?;
+        let mut options = String::new();
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            options.push(self.peek()?);
+            self.pos += 1;
+        }
+        Some(Transform {
+            regex,
+            format,
+            options,
+        })
+    }
+
+    /// The format of a transform, up to and including its closing `/`.
+    fn parse_format(&mut self) -> Option<Vec<FormatItem>> {
+        let mut items = Vec::new();
+        let mut text = String::new();
+        loop {
+            match self.peek()? {
+                '/' => {
+                    self.pos += 1;
+                    break;
+                }
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek()? {
+                        e @ ('$' | '}' | '\\' | '/') => text.push(e),
+                        c => {
+                            text.push('\\');
+                            text.push(c);
+                        }
+                    }
+                    self.pos += 1;
+                }
+                '$' => {
+                    let start = self.pos;
+                    match self.parse_format_item() {
+                        Some(item) => {
+                            if !text.is_empty() {
+                                items.push(FormatItem::Text(std::mem::take(&mut text)));
+                            }
+                            items.push(item);
+                        }
+                        None => {
+                            self.pos = start + 1;
+                            text.push('$');
+                        }
+                    }
+                }
+                c => {
+                    self.pos += 1;
+                    text.push(c);
+                }
+            }
+        }
+        if !text.is_empty() {
+            items.push(FormatItem::Text(text));
+        }
+        Some(items)
+    }
+
+    fn parse_format_item(&mut self) -> Option<FormatItem> {
+        self.pos += 1;
+        if let Some(group) = self.parse_int() {
+            return Some(FormatItem::Group(group));
+        }
+        if !self.eat('{') {
+            return None;
+        }
+        let group = self.parse_int()?;
+        if self.eat('}') {
+            return Some(FormatItem::Group(group));
+        }
+        if !self.eat(':') {
+            return None;
+        }
+        if self.eat('/') {
+            let name = self.parse_var()?;
+            let modifier = match name.as_str() {
+                "upcase" => Modifier::Upcase,
+                "downcase" => Modifier::Downcase,
+                "capitalize" => Modifier::Capitalize,
+                "camelcase" => Modifier::Camelcase,
+                "pascalcase" => Modifier::Pascalcase,
+                _ => return None,
+            };
+            return self
+                .eat('}')
+                .then_some(FormatItem::Modifier(group, modifier));
+        }
+        let (if_text, else_text) = if self.eat('+') {
+            (Some(self.parse_format_text('}')?), None)
+        } else if self.eat('?') {
+            let if_text = self.parse_format_text(':')?;
+            self.pos += 1;
+            (Some(if_text), Some(self.parse_format_text('}')?))
+        } else {
+            self.eat('-');
+            (None, Some(self.parse_format_text('}')?))
+        };
+        self.pos += 1;
+        Some(FormatItem::Conditional {
+            group,
+            if_text,
+            else_text,
+        })
+    }
+
+    /// The text of a conditional up to `end`, which is not consumed.
+    fn parse_format_text(&mut self, end: char) -> Option<String> {
+        self.scan(Scan::FormatText(end), |parser| parser.scan_format_text(end))
+    }
+
+    fn scan_format_text(&mut self, end: char) -> Option<String> {
+        let mut text = String::new();
+        loop {
+            match self.peek()? {
+                c if c == end => return Some(text),
+                '\\' => {
+                    self.pos += 1;
+                    text.push(self.peek()?);
+                    self.pos += 1;
+                }
+                c => {
+                    self.pos += 1;
+                    text.push(c);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(t: &str) -> Node {
+        Node::Text(t.to_string())
+    }
+
+    fn tabstop(index: usize) -> Node {
+        Node::Tabstop {
+            index,
+            transform: None,
+        }
+    }
+
+    #[test]
+    fn nodes() {
+        assert_eq!(
+            parse("log(${1:msg}$0)").nodes,
+            vec![
+                text("log("),
+                Node::Placeholder {
+                    index: 1,
+                    children: vec![text("msg")],
+                },
+                tabstop(0),
+                text(")"),
+            ]
+        );
+        assert_eq!(
+            parse("${1:outer ${2:inner}}").nodes,
+            vec![Node::Placeholder {
+                index: 1,
+                children: vec![
+                    text("outer "),
+                    Node::Placeholder {
+                        index: 2,
+                        children: vec![text("inner")],
+                    },
+                ],
+            }]
+        );
+        assert_eq!(
+            parse("${1|one,t\\,wo,three|}").nodes,
+            vec![Node::Choice {
+                index: 1,
+                options: vec!["one".to_string(), "t,wo".to_string(), "three".to_string()],
+            }]
+        );
+        assert_eq!(
+            parse("$TM_FILENAME ${TM_SELECTED_TEXT:${1:default}}").nodes,
+            vec![
+                Node::Variable {
+                    name: "TM_FILENAME".to_string(),
+                    default: None,
+                    transform: None,
+                },
+                text(" "),
+                Node::Variable {
+                    name: "TM_SELECTED_TEXT".to_string(),
+                    default: Some(vec![Node::Placeholder {
+                        index: 1,
+                        children: vec![text("default")],
+                    }]),
+                    transform: None,
+                },
+            ]
+        );
+        assert_eq!(parse("${2}${10}").nodes, vec![tabstop(2), tabstop(10)]);
+    }
+
+    #[test]
+    fn transforms() {
+        assert_eq!(
+            parse("${TM_FILENAME/(.*)\\.(\\w+)/${1:/upcase}_$2${3:?yes:no}\\//gi}").nodes,
+            vec![Node::Variable {
+                name: "TM_FILENAME".to_string(),
+                default: None,
+                transform: Some(Transform {
+                    regex: "(.*)\\.(\\w+)".to_string(),
+                    format: vec![
+                        FormatItem::Modifier(1, Modifier::Upcase),
+                        FormatItem::Text("_".to_string()),
+                        FormatItem::Group(2),
+                        FormatItem::Conditional {
+                            group: 3,
+                            if_text: Some("yes".to_string()),
+                            else_text: Some("no".to_string()),
+                        },
+                        FormatItem::Text("/".to_string()),
+                    ],
+                    options: "gi".to_string(),
+                }),
+            }]
+        );
+        assert_eq!(
+            parse("${1/a/${1:+x}${1:-y}${1:z}/}").nodes,
+            vec![Node::Tabstop {
+                index: 1,
+                transform: Some(Transform {
+                    regex: "a".to_string(),
+                    format: vec![
+                        FormatItem::Conditional {
+                            group: 1,
+                            if_text: Some("x".to_string()),
+                            else_text: None,
+                        },
+                        FormatItem::Conditional {
+                            group: 1,
+                            if_text: None,
+                            else_text: Some("y".to_string()),
+                        },
+                        FormatItem::Conditional {
+                            group: 1,
+                            if_text: None,
+                            else_text: Some("z".to_string()),
+                        },
+                    ],
+                    options: String::new(),
+                }),
+            }]
+        );
+    }
+
+    #[test]
+    fn escapes_and_errors() {
+        assert_eq!(parse("\\$1 \\} \\\\").nodes, vec![text("$1 } \\")]);
+        assert_eq!(parse("a\\b").nodes, vec![text("a\\b")]);
+        // malformed constructs are text
+        assert_eq!(parse("${1:foo").to_plain_text(), "${1:foo");
+        assert_eq!(parse("$ ${} ${a").to_plain_text(), "$ ${} ${a");
+        assert_eq!(parse("${1|a,b}").to_plain_text(), "${1|a,b}");
+        assert_eq!(parse("cost: 5$").to_plain_text(), "cost: 5$");
+        assert_eq!(parse("}").to_plain_text(), "}");
+        assert_eq!(
+            parse("a ${1:b ${VAR:c $2").nodes,
+            vec![text("a ${1:b ${VAR:c "), tabstop(2)]
+        );
+        assert_eq!(
+            parse("${1:${2:x}").nodes,
+            vec![
+                text("${1:"),
+                Node::Placeholder {
+                    index: 2,
+                    children: vec![text("x")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn nesting() {
+        // every unclosed level used to parse the rest of the text again
+        let deep = "${1:".repeat(5000) + "x";
+        assert_eq!(parse(&deep).nodes, vec![text(&deep)]);
+        let deep = "${1:${VAR:".repeat(2000) + "$0";
+        assert_eq!(
+            parse(&deep).nodes,
+            vec![text(&deep[..deep.len() - 2]), tabstop(0)]
+        );
+
+        let p = |depth: usize| {
+            let nested = "${1:".repeat(depth) + "x" + &"}".repeat(depth);
+            parse(&nested).to_plain_text()
+        };
+        assert_eq!(p(MAX_DEPTH), "x");
+        assert_ne!(p(MAX_DEPTH + 1), "x");
+    }
+
+    #[test]
+    fn unterminated() {
+        // every unclosed conditional used to scan the rest of the text again
+        for unit in ["${1/x/${1:+", "${1/x/${2:?a", "${1|a,", "${1/a"] {
+            let text = unit.repeat(20000);
+            assert_eq!(parse(&text).to_plain_text(), text);
+        }
+        // a later scan only fails right away when it would reach the end too
+        assert_eq!(
+            parse("${1|a ${2|b,c|}").nodes,
+            vec![
+                text("${1|a "),
+                Node::Choice {
+                    index: 2,
+                    options: vec!["b".to_owned(), "c".to_owned()],
+                },
+            ]
+        );
+        assert_eq!(
+            parse("${1|a|x ${2|b|}").nodes,
+            vec![
+                text("${1|a|x "),
+                Node::Choice {
+                    index: 2,
+                    options: vec!["b".to_owned()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_text() {
+        let p = |s: &str| parse(s).to_plain_text();
+        assert_eq!(p("log(${1:msg}$0)"), "log(msg)");
+        assert_eq!(p("${1|one,two|} ${2:a ${3:b}}"), "one a b");
+        assert_eq!(p("fn ${1:name}(${2}) {\n\t$0\n}"), "fn name() {\n\t\n}");
+        assert_eq!(p("$TM_FILENAME${VAR:default}"), "default");
+        assert_eq!(p("${1/(.*)/$1/}x"), "x");
+    }
+}
@@ -1,58 +1,363 @@
 use std::collections::HashMap;
 
 use mlua::prelude::*;
+use rayon::prelude::*;
+use serde::Deserialize;
 
-use crate::{context::Context, entry::Entry};
+use crate::{
+    context::{Context, ContextReason},
+    entry::{EntryData, ItemDefaultsCache},
+    utils::{
+        matcher::{MatchRegion, MatcherConfig, Query, Strategy},
+        misc,
+    },
+};
 
+/// Options of `get_entries_from_source`, set from Lua with the `filter` table of `setup`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FilterOption {
+    /// Match the entries on a thread pool when a source has at least this many of them,
+    /// 0 disables it
+    pub parallel_threshold: usize,
+
+    /// Add the display columns and UTF-16 offsets to the matched regions, see
+    /// `MatchRegion::to_lua_with_positions`
+    pub positions: bool,
+
+    /// Turn on `MatchingOption::smart_case`, whatever the `matching` options of cmp say
+    pub smart_case: bool,
+
+    /// Matching strategy per source name, e.g. `{ path = "path" }`
+    pub strategies: HashMap<String, Strategy>,
+
+    /// Only match the entries that matched the last time when the input grows, see `SourceCache`
+    pub cache: bool,
+}
+
+impl Default for FilterOption {
+    fn default() -> Self {
+        Self {
+            parallel_threshold: 1000,
+            positions: false,
+            smart_case: false,
+            strategies: HashMap::new(),
+            cache: true,
+        }
+    }
+}
+
+/// What `get_entries` remembers of the last filtering of a source.
+///
+/// When the user types one more char, only the entries that matched the shorter input, or that
+/// a longer input may match (see `Query::may_match_longer`), can still match, so the others
+/// are skipped.
+struct SourceCache {
+    /// The `entries` table of the source, to notice when it is replaced
+    entries: LuaRegistryKey,
+    entries_len: i64,
+    bufnr: i32,
+    row: i32,
+    cursor_before_line: String,
+    config: MatcherConfig,
+
+    /// Lua indices of the entries that matched or may match a longer input, in order
+    survivors: Vec<i64>,
+}
+
+/// The `SourceCache` of every source, by source id, in the Lua app data.
+#[derive(Default)]
+struct FilterCache(HashMap<i64, SourceCache>);
+
+/// The entries of the source that can still match, if the cache of the source is valid.
+fn cached_survivors(
+    lua: &Lua,
+    source_id: i64,
+    target_entries: &LuaTable,
+    target_len: i64,
+    ctx: &Context,
+    config: &MatcherConfig,
+) -> LuaResult<Option<Vec<i64>>> {
+    // more typos are allowed as the input grows
+    if ctx.option.reason == ContextReason::Manual || config.matching.max_typos > 0 {
+        return Ok(None);
+    }
+    let cache = match lua.app_data_ref::<FilterCache>() {
+        Some(cache) => cache,
+        None => return Ok(None),
+    };
+    let c = match cache.0.get(&source_id) {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    if c.entries_len != target_len
+        || c.bufnr != ctx.bufnr
+        || c.row != ctx.cursor.row
+        || !ctx.cursor_before_line.starts_with(&c.cursor_before_line)
+        || c.config != *config
+        || lua.registry_value::<LuaTable>(&c.entries)? != *target_entries
+    {
+        return Ok(None);
+    }
+    Ok(Some(c.survivors.clone()))
+}
+
+fn cache_survivors(
+    lua: &Lua,
+    source_id: i64,
+    target_entries: LuaTable,
+    target_len: i64,
+    ctx: &Context,
+    config: &MatcherConfig,
+    survivors: Vec<i64>,
+) -> LuaResult<()> {
+    let c = SourceCache {
+        entries: lua.create_registry_value(target_entries)?,
+        entries_len: target_len,
+        bufnr: ctx.bufnr,
+        row: ctx.cursor.row,
+        cursor_before_line: ctx.cursor_before_line.clone(),
+        config: config.clone(),
+        survivors,
+    };
+    if lua.app_data_ref::<FilterCache>().is_none() {
+        lua.set_app_data(FilterCache::default());
+    }
+    let old = lua
+        .app_data_mut::<FilterCache>()
+        .unwrap()
+        .0
+        .insert(source_id, c);
+    if let Some(old) = old {
+        lua.remove_registry_value(old.entries)?;
+    }
+    Ok(())
+}
+
+fn get_input(ctx: &Context, offset: i32) -> &str {
+    if offset >= 0 && ctx.cursor_before_line.len() >= offset as usize {
+        &ctx.cursor_before_line.as_str()[(offset - 1) as usize..]
+    } else {
+        ""
+    }
+}
+
+/// A matched entry, written back to Lua only if it is among the `limit` best ones.
+struct Survivor {
+    /// Lua index of the entry
+    index: i64,
+    matched: (f64, Vec<MatchRegion>),
+    exact: bool,
+
+    /// The input and the label, when the regions need their `RegionPositions`
+    positions: Option<(String, String)>,
+}
+
+impl Survivor {
+    fn new(
+        index: i64,
+        matched: (f64, Vec<MatchRegion>),
+        exact: bool,
+        input: &str,
+        e: &EntryData,
+        option: &FilterOption,
+    ) -> Self {
+        Self {
+            index,
+            matched,
+            exact,
+            positions: if option.positions {
+                Some((input.to_owned(), e.get_label().to_owned()))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Sets the result of `EntryData::do_match`, whose regions are in the label.
+    fn set_matched(&self, lua: &Lua, entry: &LuaTable) -> LuaResult<()> {
+        entry.set("score", self.matched.0)?;
+        let matches = lua.create_table()?;
+        for (i, m) in self.matched.1.iter().enumerate() {
+            let m_lua = match &self.positions {
+                Some((input, label)) => m.to_lua_with_positions(lua, input, label)?,
+                None => m.to_lua(lua)?,
+            };
+            matches.set(i + 1, m_lua)?;
+        }
+        entry.set("matches", matches)?;
+        entry.set("exact", self.exact)?;
+        Ok(())
+    }
+}
+
+/// Matches all the entries of the source and returns the `limit` best ones, sorted by score.
 pub fn get_entries<'a>(
     lua: &'a Lua,
     source: &LuaTable,
     ctx: &Context,
     limit: i64,
+    config: &MatcherConfig,
+    option: &FilterOption,
 ) -> LuaResult<LuaTable<'a>> {
     let target_entries: LuaTable = source.get("entries")?;
     let target_len = target_entries.raw_len();
-    let mut inputs = HashMap::<i32, &str>::new();
-    let entries = lua.create_table()?;
-    let mut num_entry = 1i32;
-    for i in 1..(std::cmp::min(target_len, limit) + 1) {
-        let e_lua = target_entries.get::<_, LuaValue>(i)?;
-        let mut e = Entry::from_lua(e_lua, lua)?;
-        let o = e.get_offset()?;
-        let input =
-            inputs
-                .entry(o)
-                .or_insert(if o >= 0 && ctx.cursor_before_line.len() >= o as usize {
-                    &ctx.cursor_before_line.as_str()[(o - 1) as usize..]
-                } else {
-                    ""
-                });
-        // ::log::debug!(
-        //     "bbb cursor_before_line: {}, len: {}, input: {}, offset: {}",
-        //     ctx.cursor_before_line,
-        //     ctx.cursor_before_line.len(),
-        //     input, 
-        //     o
-        // );
-        let matched = e.do_match(input)?;
-        let score = matched.0;
-        e.entry.set("score", score)?;
-        e.entry.set("exact", false)?;
-        if score >= 1.0 {
-            let matches = lua.create_table()?;
-            let mut count: i32 = 1;
-            for m in matched.1 {
-                let m_lua: LuaTable = m.to_lua(lua)?;
-                matches.set(count, m_lua)?;
-                count += 1;
-            }
-            e.entry.set("matches", matches)?;
-            let eq_filter_text = &e.get_filter_text() == input;
-            let eq_word = &e.get_word()? == input;
-            e.entry.set("exact", eq_filter_text || eq_word)?;
-            entries.set(num_entry, e.entry)?;
-            num_entry += 1;
+    let mut config = config.clone();
+    if let Some(name) = source.get::<_, Option<String>>("name")? {
+        if let Some(strategy) = option.strategies.get(&name) {
+            config.strategy = *strategy;
+        }
+    }
+    let config = &config;
+
+    let source_id = if option.cache {
+        source.get::<_, Option<i64>>("id")?
+    } else {
+        None
+    };
+    let indices = match source_id {
+        Some(id) => cached_survivors(lua, id, &target_entries, target_len, ctx, config)?,
+        None => None,
+    }
+    .unwrap_or_else(|| (1..(target_len + 1)).collect());
+
+    let matched = if option.parallel_threshold > 0 && indices.len() >= option.parallel_threshold {
+        match_entries_parallel(lua, &target_entries, &indices, ctx, config, option)?
+    } else {
+        match_entries(lua, &target_entries, &indices, ctx, config, option)?
+    };
+    let mut survivors = Vec::new();
+    let mut pending = Vec::new();
+    for m in matched {
+        match m {
+            Matched::Survivor(survivor) => survivors.push(survivor),
+            Matched::Pending(index) => pending.push(index),
+            Matched::Rejected => {}
         }
     }
+    if let Some(id) = source_id {
+        let mut indices: Vec<i64> = survivors.iter().map(|s| s.index).collect();
+        if !pending.is_empty() {
+            indices.extend(pending);
+            indices.sort_unstable();
+        }
+        cache_survivors(
+            lua,
+            id,
+            target_entries.clone(),
+            target_len,
+            ctx,
+            config,
+            indices,
+        )?;
+    }
+
+    let limit = std::cmp::max(limit, 0) as usize;
+    let entries =
+        lua.create_table_with_capacity(std::cmp::min(survivors.len(), limit) as i32, 0)?;
+    for (i, survivor) in misc::top_k(survivors, limit, |s| s.matched.0)
+        .into_iter()
+        .enumerate()
+    {
+        let e_lua: LuaTable = target_entries.get(survivor.index)?;
+        survivor.set_matched(lua, &e_lua)?;
+        entries.set(i + 1, e_lua)?;
+    }
     Ok(entries)
 }
+
+/// The input at the offset and its query.
+fn new_query<'a>(ctx: &'a Context, offset: i32, config: &MatcherConfig) -> (&'a str, Query) {
+    let input = get_input(ctx, offset);
+    (input, Query::new(input, config))
+}
+
+/// What `match_entry` found for an entry.
+enum Matched {
+    Survivor(Survivor),
+
+    /// The entry doesn't match, but a longer input may match it, see `SourceCache`
+    Pending(i64),
+
+    Rejected,
+}
+
+/// Matches an entry with the query of its offset, the same way on both paths.
+fn match_entry(
+    e: &mut EntryData,
+    index: i64,
+    (input, query): &(&str, Query),
+    option: &FilterOption,
+) -> LuaResult<Matched> {
+    let matched = e.do_match(query)?;
+    if matched.0 > 0.0 {
+        let exact = e.get_filter_text() == *input || e.get_word()? == *input;
+        Ok(Matched::Survivor(Survivor::new(
+            index, matched, exact, input, e, option,
+        )))
+    } else if option.cache && e.may_match_longer(query)? {
+        Ok(Matched::Pending(index))
+    } else {
+        Ok(Matched::Rejected)
+    }
+}
+
+/// Matches the entries at the given Lua indices.
+fn match_entries(
+    lua: &Lua,
+    target_entries: &LuaTable,
+    indices: &[i64],
+    ctx: &Context,
+    config: &MatcherConfig,
+    option: &FilterOption,
+) -> LuaResult<Vec<Matched>> {
+    let mut queries = HashMap::<i32, (&str, Query)>::new();
+    let mut matched = Vec::with_capacity(indices.len());
+    let mut defaults = ItemDefaultsCache::default();
+    for i in indices {
+        let e_lua: LuaTable = target_entries.get(*i)?;
+        let mut e = EntryData::new(&e_lua, lua, &mut defaults)?;
+        let o = e.get_offset()?;
+        let query = queries
+            .entry(o)
+            .or_insert_with(|| new_query(ctx, o, config));
+        matched.push(match_entry(&mut e, *i, query, option)?);
+    }
+    Ok(matched)
+}
+
+/// Like `match_entries`, but the matching runs on the rayon thread pool.
+///
+/// Only converting the entries happens on the main thread.
+fn match_entries_parallel(
+    lua: &Lua,
+    target_entries: &LuaTable,
+    indices: &[i64],
+    ctx: &Context,
+    config: &MatcherConfig,
+    option: &FilterOption,
+) -> LuaResult<Vec<Matched>> {
+    let mut data = Vec::<EntryData>::with_capacity(indices.len());
+    let mut defaults = ItemDefaultsCache::default();
+    for i in indices {
+        let e_lua: LuaTable = target_entries.get(*i)?;
+        data.push(EntryData::new(&e_lua, lua, &mut defaults)?);
+    }
+
+    let offsets = data
+        .par_iter_mut()
+        .map(|e| e.get_offset())
+        .collect::<LuaResult<Vec<i32>>>()?;
+    let mut queries = HashMap::<i32, (&str, Query)>::new();
+    for o in &offsets {
+        queries
+            .entry(*o)
+            .or_insert_with(|| new_query(ctx, *o, config));
+    }
+
+    data.par_iter_mut()
+        .zip(offsets.par_iter())
+        .zip(indices.par_iter())
+        .map(|((e, o), i)| match_entry(e, *i, &queries[o], option))
+        .collect()
+}
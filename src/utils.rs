@@ -1,4 +1,9 @@
 pub mod byte_char;
+pub mod char_class;
+pub mod extended;
+pub mod fzy;
 pub mod matcher;
 pub mod misc;
+pub mod path;
 pub mod str_utils;
+pub mod unicode_char;
@@ -50,74 +50,17 @@ pub fn is_symbol(byte: u8) -> bool {
     !(is_alnum(byte) || is_white(byte))
 }
 
-pub fn is_semantic_index(text: &[u8], index: usize) -> bool {
-    if index < 1 {
-        return true;
-    }
-    let bytes = text;
-    let prev = bytes[index - 1];
-    let curr = bytes[index];
-    if !is_upper(prev) && is_upper(curr) {
-        return true;
-    }
-    if !is_upper(prev) && is_upper(curr) {
-        return true;
-    }
-    if is_symbol(curr) || is_white(curr) {
-        return true;
-    }
-    if !is_alpha(prev) && is_alpha(curr) {
-        return true;
-    }
-    if !is_digit(prev) && is_digit(curr) {
-        return true;
-    }
-    return false;
-}
-
-pub fn get_next_semantic_index(text: &[u8], current_index: usize) -> usize {
-    for i in current_index + 1..text.len() {
-        if is_semantic_index(text, i) {
-            return i;
-        }
-    }
-    return text.len();
-}
-
 pub fn get_real_idx(len: usize, idx: i32) -> usize {
     let ll = len as i32;
     ((ll + idx) % ll) as usize
 }
 
-pub fn match_char(byte1: u8, byte2: u8) -> bool {
-    if !is_alpha(byte1) || !is_alpha(byte2) {
-        return byte1 == byte2;
-    }
-    let diff = if byte1 > byte2 {
-        byte1 - byte2
-    } else {
-        byte2 - byte1
-    };
-    diff == 0 || diff == 32
-}
-
-pub fn has_prefix(text: &[u8], prefix: &[u8]) -> bool {
-    if text.len() < prefix.len() {
-        return false;
-    }
-    for i in 0..prefix.len() {
-        if !match_char(text[i], prefix[i]) {
-            return false;
-        }
-    }
-    return true;
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
     #[test]
     pub fn basic_cases() {
-        assert_eq!(get_next_semantic_index("a".as_bytes(), 0), 1);
+        assert_eq!(get_real_idx(3, -1), 2);
+        assert!(is_symbol(b'_') && !is_symbol(b' ') && !is_symbol(b'a'));
     }
 }
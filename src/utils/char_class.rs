@@ -0,0 +1,216 @@
+//! Identifier chars per filetype.
+//!
+//! Out of the box, any symbol ends an identifier and is a word boundary. Some languages use
+//! symbols in identifiers though, like `-` in CSS (`border-radius`) or `#` in Vim script
+//! (`dein#get`). The `keyword` chars of a `CharClass` are not symbols: they don't end the words
+//! extracted from completion items and are not word boundaries themselves, the letter after
+//! them still is.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use mlua::prelude::*;
+use serde::Deserialize;
+
+use crate::utils::{byte_char, str_utils, unicode_char};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CharClass {
+    /// Symbols that are part of identifiers, e.g. `"#:"` for Vim script
+    pub keyword: String,
+}
+
+impl CharClass {
+    pub fn new(keyword: &str) -> Self {
+        Self {
+            keyword: keyword.to_owned(),
+        }
+    }
+
+    pub fn is_keyword(&self, c: char) -> bool {
+        self.keyword.contains(c)
+    }
+
+    /// `unicode_char::is_symbol` without the keyword chars.
+    pub fn is_symbol(&self, c: char) -> bool {
+        unicode_char::is_symbol(c) && !self.is_keyword(c)
+    }
+
+    /// Whether a word starts at the index: at an uppercase letter after another char, a symbol,
+    /// a whitespace or the first letter or digit of a run. The keyword chars are part of
+    /// identifiers.
+    pub fn is_semantic_index(&self, text: &[char], index: usize) -> bool {
+        if index < 1 {
+            return true;
+        }
+        let prev = text[index - 1];
+        let curr = text[index];
+        if !unicode_char::is_upper(prev) && unicode_char::is_upper(curr) {
+            return true;
+        }
+        if self.is_symbol(curr) || unicode_char::is_white(curr) {
+            return true;
+        }
+        // a keyword char starts an identifier, e.g. `$` of `($var`
+        if self.is_keyword(curr) && !unicode_char::is_alnum(prev) && !self.is_keyword(prev) {
+            return true;
+        }
+        if !unicode_char::is_alpha(prev) && unicode_char::is_alpha(curr) {
+            return true;
+        }
+        if !unicode_char::is_digit(prev) && unicode_char::is_digit(curr) {
+            return true;
+        }
+        false
+    }
+
+    pub fn get_next_semantic_index(&self, text: &[char], current_index: usize) -> usize {
+        for i in current_index + 1..text.len() {
+            if self.is_semantic_index(text, i) {
+                return i;
+            }
+        }
+        text.len()
+    }
+
+    pub fn is_byte_keyword(&self, byte: u8) -> bool {
+        byte.is_ascii() && self.is_keyword(byte as char)
+    }
+
+    /// `is_semantic_index` on the bytes of a line, non-ASCII bytes are symbols.
+    pub fn is_semantic_byte_index(&self, text: &[u8], index: usize) -> bool {
+        if index < 1 {
+            return true;
+        }
+        let prev = text[index - 1];
+        let curr = text[index];
+        if !byte_char::is_upper(prev) && byte_char::is_upper(curr) {
+            return true;
+        }
+        if byte_char::is_symbol(curr) && !self.is_byte_keyword(curr) || byte_char::is_white(curr) {
+            return true;
+        }
+        if self.is_byte_keyword(curr) && !byte_char::is_alnum(prev) && !self.is_byte_keyword(prev) {
+            return true;
+        }
+        if !byte_char::is_alpha(prev) && byte_char::is_alpha(curr) {
+            return true;
+        }
+        if !byte_char::is_digit(prev) && byte_char::is_digit(curr) {
+            return true;
+        }
+        false
+    }
+
+    /// `str_utils::is_invalid_chars` without the keyword chars.
+    pub fn is_invalid_char(&self, byte: u8) -> bool {
+        str_utils::is_invalid_chars(byte) && !self.is_byte_keyword(byte)
+    }
+}
+
+/// The `CharClass` of every filetype, in the Lua app data.
+#[derive(Clone, Debug)]
+pub struct CharClasses(HashMap<String, Arc<CharClass>>);
+
+impl Default for CharClasses {
+    fn default() -> Self {
+        let mut classes = HashMap::new();
+        let mut add = |filetypes: &[&str], keyword: &str| {
+            let class = Arc::new(CharClass::new(keyword));
+            for filetype in filetypes {
+                classes.insert(filetype.to_string(), class.clone());
+            }
+        };
+        add(&["css", "scss", "less", "lisp", "clojure", "scheme"], "-");
+        add(&["php", "perl"], "$");
+        add(&["vim"], "#:");
+        Self(classes)
+    }
+}
+
+impl CharClasses {
+    /// Adds or replaces the classes of a Lua table of filetypes, e.g.
+    /// `{ sh = { keyword = "-" } }`.
+    pub fn configure(lua: &Lua, value: LuaValue) -> LuaResult<()> {
+        let classes: HashMap<String, CharClass> = lua.from_value(value)?;
+        let mut all = lua
+            .app_data_ref::<CharClasses>()
+            .map(|c| c.clone())
+            .unwrap_or_default();
+        for (filetype, class) in classes {
+            all.0.insert(filetype, Arc::new(class));
+        }
+        lua.set_app_data(all);
+        Ok(())
+    }
+
+    /// The class of the filetype, set by `configure` or built in.
+    pub fn from_app_data(lua: &Lua, filetype: Option<&str>) -> Arc<CharClass> {
+        match lua.app_data_ref::<CharClasses>() {
+            Some(classes) => classes.get(filetype),
+            None => Self::default().get(filetype),
+        }
+    }
+
+    pub fn get(&self, filetype: Option<&str>) -> Arc<CharClass> {
+        filetype
+            .and_then(|filetype| self.0.get(filetype))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn boundaries(class: &CharClass, text: &str) -> Vec<usize> {
+        let chars: Vec<char> = text.chars().collect();
+        (0..chars.len())
+            .filter(|i| class.is_semantic_index(&chars, *i))
+            .collect()
+    }
+
+    #[test]
+    fn semantic_index() {
+        let none = CharClass::default();
+        let vim = CharClass::new("#:");
+        let php = CharClass::new("$");
+        assert_eq!(boundaries(&none, "dein#get"), vec![0, 4, 5]);
+        assert_eq!(boundaries(&vim, "dein#get"), vec![0, 5]);
+        assert_eq!(boundaries(&vim, "g:var"), vec![0, 2]);
+        assert_eq!(boundaries(&php, "($this"), vec![0, 1, 2]);
+        assert_eq!(boundaries(&php, "a$$b"), vec![0, 3]);
+        assert_eq!(vim.get_next_semantic_index(&['a', '#', 'b'], 0), 2);
+        assert_eq!(boundaries(&none, "ärgerÜber_straße"), vec![0, 5, 9, 10]);
+        for text in ["dein#get", "g:var", "($this", "border-radius", "a$$b"] {
+            let chars: Vec<char> = text.chars().collect();
+            for class in [&none, &vim, &php] {
+                for i in 0..chars.len() {
+                    assert_eq!(
+                        class.is_semantic_index(&chars, i),
+                        class.is_semantic_byte_index(text.as_bytes(), i),
+                        "{} at {}",
+                        text,
+                        i
+                    );
+                }
+            }
+        }
+        assert!(none.is_symbol('-') && !CharClass::new("-").is_symbol('-'));
+    }
+
+    #[test]
+    fn filetypes() {
+        let classes = CharClasses::default();
+        assert_eq!(classes.get(Some("css")).keyword, "-");
+        assert_eq!(classes.get(Some("php")).keyword, "$");
+        assert_eq!(classes.get(Some("vim")).keyword, "#:");
+        assert_eq!(*classes.get(Some("rust")), CharClass::default());
+        assert_eq!(*classes.get(None), CharClass::default());
+
+        assert!(CharClass::default().is_invalid_char(b'$'));
+        assert!(!classes.get(Some("php")).is_invalid_char(b'$'));
+    }
+}
@@ -0,0 +1,326 @@
+//! fzf's extended search syntax on top of `matcher::do_match`.
+//!
+//! | Term     | Meaning                           |
+//! | -------- | --------------------------------- |
+//! | `foo`    | fuzzy match (`do_match`)          |
+//! | `'foo`   | contains `foo`                    |
+//! | `^foo`   | starts with `foo`                 |
+//! | `foo$`   | ends with `foo`                   |
+//! | `^foo$`  | is `foo`                          |
+//! | `!foo`   | does not contain `foo`            |
+//! | `!^foo`  | does not start with `foo`         |
+//! | `!foo$`  | does not end with `foo`           |
+//!
+//! Terms are separated by spaces (`\ ` is a literal space) and all of them have to match.
+//! Everything but the fuzzy terms compares case-insensitively, and without diacritics when
+//! `ignore_diacritics` is set.
+
+use crate::utils::matcher::{self, Chars, MatchRegion, MatcherConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermKind {
+    Fuzzy,
+    Exact,
+    Prefix,
+    Suffix,
+    Equal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    pub kind: TermKind,
+    pub negated: bool,
+    pub text: String,
+
+    /// Byte offset in the whole input of every char of `text`, followed by the end of the term.
+    /// An escaped space is one char of `text` but two of the input.
+    pub offsets: Vec<usize>,
+}
+
+impl Term {
+    /// Maps a byte offset of `text` to the whole input.
+    pub fn input_offset(&self, text_offset: usize) -> usize {
+        let index = self
+            .text
+            .char_indices()
+            .position(|(i, _)| i == text_offset)
+            .unwrap_or(self.offsets.len() - 1);
+        self.offsets[index]
+    }
+}
+
+/// Splits an extended query into its terms.
+pub fn parse(input: &str) -> Vec<Term> {
+    let mut terms = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c == ' ' {
+            continue;
+        }
+        // read the raw term, unescaping `\ `
+        let mut raw = vec![(start, c)];
+        if c == '\\' && chars.peek().map(|(_, c)| *c) == Some(' ') {
+            raw[0] = chars.next().unwrap();
+        }
+        while let Some((i, c)) = chars.next() {
+            if c == ' ' {
+                break;
+            }
+            if c == '\\' && chars.peek().map(|(_, c)| *c) == Some(' ') {
+                raw.push(chars.next().unwrap());
+            } else {
+                raw.push((i, c));
+            }
+        }
+        if let Some(term) = to_term(&raw) {
+            terms.push(term);
+        }
+    }
+    terms
+}
+
+fn to_term(raw: &[(usize, char)]) -> Option<Term> {
+    let mut raw = raw;
+    let negated = raw[0].1 == '!';
+    if negated {
+        raw = &raw[1..];
+    }
+    let mut kind = if negated {
+        TermKind::Exact
+    } else {
+        TermKind::Fuzzy
+    };
+    match raw.first() {
+        Some((_, '\'')) => {
+            kind = TermKind::Exact;
+            raw = &raw[1..];
+        }
+        Some((_, '^')) => {
+            kind = TermKind::Prefix;
+            raw = &raw[1..];
+        }
+        _ => {}
+    }
+    if raw.len() > 1 && raw[raw.len() - 1].1 == '$' {
+        kind = if kind == TermKind::Prefix {
+            TermKind::Equal
+        } else {
+            TermKind::Suffix
+        };
+        raw = &raw[..raw.len() - 1];
+    }
+    // a lone `!`, `'` or `^` matches everything
+    if raw.is_empty() {
+        return None;
+    }
+    let (last, c) = raw[raw.len() - 1];
+    Some(Term {
+        kind,
+        negated,
+        text: raw.iter().map(|(_, c)| c).collect(),
+        offsets: raw
+            .iter()
+            .map(|(i, _)| *i)
+            .chain(std::iter::once(last + c.len_utf8()))
+            .collect(),
+    })
+}
+
+/// Matches an extended query against the word.
+///
+/// The score is the sum of the scores of the terms, and the regions of all the terms are merged
+/// into non-overlapping regions of the word. A query with only negated terms scores 1.
+pub fn match_extended(
+    input: &str,
+    word: &str,
+    words: &[&str],
+    config: &MatcherConfig,
+) -> (f64, Vec<MatchRegion>) {
+    let terms = parse(input);
+    let mut word_chars = Chars::new(word);
+    if config.matching.ignore_diacritics {
+        word_chars = word_chars.fold_diacritics();
+    }
+    let mut score = 0.0;
+    let mut regions = Vec::new();
+    for term in &terms {
+        let (term_score, term_regions) = match term.kind {
+            TermKind::Fuzzy => matcher::do_match(&term.text, word, words, config),
+            _ => match_exact(term, &word_chars, config),
+        };
+        let matched = term_score > 0.0;
+        if matched == term.negated {
+            return (0.0, Vec::new());
+        }
+        if !term.negated {
+            score += term_score;
+            regions.extend(term_regions.into_iter().map(|mut m| {
+                m.input_match_start = term.input_offset(m.input_match_start);
+                m.input_match_end = term.input_offset(m.input_match_end);
+                m
+            }));
+        }
+    }
+    if score == 0.0 {
+        return (1.0, Vec::new());
+    }
+    (score, matcher::merge_regions(regions))
+}
+
+/// Matches a non-fuzzy term, ignoring case.
+///
+/// The score counts the matched chars like `do_match`, with the `strict_ratio` and prefix
+/// bonuses.
+fn match_exact(term: &Term, word: &Chars, config: &MatcherConfig) -> (f64, Vec<MatchRegion>) {
+    let mut text = Chars::new(&term.text);
+    if config.matching.ignore_diacritics {
+        text = text.fold_diacritics();
+    }
+    let n = text.len();
+    if n > word.len() {
+        return (0.0, Vec::new());
+    }
+    let at = |start: usize| word.folded[start..start + n] == text.folded[..];
+    let start = match term.kind {
+        TermKind::Prefix => Some(0).filter(|s| at(*s)),
+        TermKind::Suffix => Some(word.len() - n).filter(|s| at(*s)),
+        TermKind::Equal => Some(0).filter(|s| n == word.len() && at(*s)),
+        // prefer an occurrence at a word boundary
+        _ => {
+            let mut found = (0..=word.len() - n).filter(|s| at(*s));
+            let first = found.next();
+            first
+                .into_iter()
+                .chain(found)
+                .find(|s| config.char_class.is_semantic_index(&word.chars, *s))
+                .or(first)
+        }
+    };
+    let start = match start {
+        Some(start) => start,
+        None => return (0.0, Vec::new()),
+    };
+    let strict_count = (0..n)
+        .filter(|i| text.chars[*i] == word.chars[start + i])
+        .count();
+    let strict_ratio = strict_count as f64 / n as f64;
+    let mut score = n as f64 * (1.0 + strict_ratio);
+    if start == 0 {
+        score += config.prefix;
+    }
+    let region = MatchRegion {
+        input_match_start: 0,
+        input_match_end: text.offsets[n],
+        word_match_start: word.offsets[start],
+        word_match_end: word.offsets[start + n],
+        strict_ratio,
+        fuzzy: false,
+        typo: false,
+        index: 0,
+    };
+    (score, vec![region])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ranges(word: &str, matched: &(f64, Vec<MatchRegion>)) -> Vec<String> {
+        matched
+            .1
+            .iter()
+            .map(|m| word[m.word_match_start..m.word_match_end].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn parsing() {
+        let terms = parse("foo 'bar ^baz qux$ ^eq$ !neg !^np !ns$ a\\ b");
+        let kinds: Vec<(TermKind, bool, &str, usize)> = terms
+            .iter()
+            .map(|t| (t.kind, t.negated, t.text.as_str(), t.offsets[0]))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (TermKind::Fuzzy, false, "foo", 0),
+                (TermKind::Exact, false, "bar", 5),
+                (TermKind::Prefix, false, "baz", 10),
+                (TermKind::Suffix, false, "qux", 14),
+                (TermKind::Equal, false, "eq", 20),
+                (TermKind::Exact, true, "neg", 25),
+                (TermKind::Prefix, true, "np", 31),
+                (TermKind::Suffix, true, "ns", 35),
+                (TermKind::Fuzzy, false, "a b", 39),
+            ]
+        );
+        assert_eq!(terms[8].offsets, vec![39, 41, 42, 43]);
+        assert!(parse("  ! ' ^ ").is_empty());
+        assert_eq!(parse("$")[0].text, "$");
+    }
+
+    #[test]
+    fn matching() {
+        let config = MatcherConfig::default();
+        let m = |input: &str, word: &str| match_extended(input, word, &[], &config);
+
+        assert!(m("^buf", "BufferLine").0 > 0.0);
+        assert!(m("^line", "BufferLine").0 == 0.0);
+        assert!(m("line$", "BufferLine").0 > 0.0);
+        assert!(m("buf$", "BufferLine").0 == 0.0);
+        assert!(m("^bufferline$", "BufferLine").0 > 0.0);
+        assert!(m("^buffer$", "BufferLine").0 == 0.0);
+        assert!(m("'ferl", "BufferLine").0 > 0.0);
+        assert!(m("'fl", "BufferLine").0 == 0.0);
+        assert!(m("!line", "BufferLine").0 == 0.0);
+        assert!(m("!tab", "BufferLine").0 == 1.0);
+        assert!(m("^buf !tab", "BufferLine").0 > 0.0);
+        assert!(m("^buf !^tab", "BufferLine").0 > 0.0);
+        assert!(m("^buf !ine$", "BufferLine").0 == 0.0);
+
+        // all the terms have to match
+        assert!(m("buf line", "BufferLine").0 > 0.0);
+        assert!(m("buf tab", "BufferLine").0 == 0.0);
+
+        // regions are merged and sorted
+        let word = "buffer_line_number";
+        let r = m("num ^buf 'ffer_l", word);
+        assert_eq!(ranges(word, &r), vec!["buffer_l", "num"]);
+        assert_eq!(r.1[0].input_match_start, 5);
+        assert_eq!(r.1[0].input_match_end, 16);
+        assert_eq!(r.1[1].input_match_start, 0);
+        assert_eq!(r.1[1].input_match_end, 3);
+
+        // the regions cover the escapes of the input
+        let input = "'b\\ c x";
+        let r = m(input, "ab c_x");
+        let matched: Vec<&str> =
+            r.1.iter()
+                .map(|m| &input[m.input_match_start..m.input_match_end])
+                .collect();
+        assert_eq!(matched, vec!["b\\ c", "x"]);
+
+        // exact terms prefer word boundaries
+        let word = "counter_count";
+        let r = m("'count", word);
+        assert_eq!(r.1[0].word_match_start, 0);
+        let word = "recount_count";
+        let r = m("'count", word);
+        assert_eq!(r.1[0].word_match_start, 8);
+    }
+
+    #[test]
+    fn unicode() {
+        let config = MatcherConfig::default();
+        let word = "Ärger_über";
+        let r = match_extended("^är 'ÜBER", word, &[], &config);
+        assert_eq!(ranges(word, &r), vec!["Är", "über"]);
+        assert!(match_extended("!über$", word, &[], &config).0 == 0.0);
+        assert!(match_extended("^arger", word, &[], &config).0 == 0.0);
+
+        let mut config = MatcherConfig::default();
+        config.matching.ignore_diacritics = true;
+        let r = match_extended("^arger uber$", word, &[], &config);
+        assert_eq!(ranges(word, &r), vec!["Ärger", "über"]);
+    }
+}
@@ -0,0 +1,227 @@
+//! The scoring of [fzy](https://github.com/jhawthorn/fzy/blob/master/ALGORITHM.md).
+//!
+//! Instead of taking the first match per word boundary like `matcher::do_match`, it finds the
+//! alignment of the input with the best score: consecutive matches and matches after a
+//! separator score high, gaps between the matched chars cost a little.
+
+use crate::utils::char_class::CharClass;
+use crate::utils::matcher::{Chars, MatchRegion};
+use crate::utils::unicode_char;
+
+const SCORE_GAP_LEADING: f64 = -0.005;
+const SCORE_GAP_TRAILING: f64 = -0.005;
+const SCORE_GAP_INNER: f64 = -0.01;
+const SCORE_MATCH_CONSECUTIVE: f64 = 1.0;
+const SCORE_MATCH_SLASH: f64 = 0.9;
+const SCORE_MATCH_WORD: f64 = 0.8;
+const SCORE_MATCH_CAPITAL: f64 = 0.7;
+const SCORE_MATCH_DOT: f64 = 0.6;
+
+/// Longer words are matched without looking for the best alignment.
+const MATCH_MAX_LEN: usize = 1024;
+
+/// Bonus for matching `c` when it follows `prev`.
+fn bonus(prev: char, c: char) -> f64 {
+    if !unicode_char::is_alnum(c) {
+        return 0.0;
+    }
+    match prev {
+        '/' => SCORE_MATCH_SLASH,
+        '-' | '_' | ' ' => SCORE_MATCH_WORD,
+        '.' => SCORE_MATCH_DOT,
+        _ if unicode_char::is_upper(c)
+            && !unicode_char::is_upper(prev)
+            && unicode_char::is_alpha(prev) =>
+        {
+            SCORE_MATCH_CAPITAL
+        }
+        _ => 0.0,
+    }
+}
+
+/// Whether every char of the input is in the word, in order.
+pub fn has_match(input: &Chars, word: &Chars) -> bool {
+    let mut j = 0;
+    for i in 0..input.len() {
+        while j < word.len() && !input.match_char(i, word, j) {
+            j += 1;
+        }
+        if j == word.len() {
+            return false;
+        }
+        j += 1;
+    }
+    true
+}
+
+/// The fzy score of the input in the word and the char indices of the matched chars.
+///
+/// The score is infinite for an exact match, and negative infinity without positions when the
+/// word is too long. `has_match` has to be checked first.
+pub fn score(input: &Chars, word: &Chars) -> (f64, Vec<usize>) {
+    let n = input.len();
+    let m = word.len();
+    if n == m {
+        return (f64::INFINITY, (0..n).collect());
+    }
+    if n == 0 || m > MATCH_MAX_LEN {
+        return (f64::NEG_INFINITY, Vec::new());
+    }
+
+    let mut prev = '/';
+    let bonuses: Vec<f64> = word
+        .chars
+        .iter()
+        .map(|c| {
+            let b = bonus(prev, *c);
+            prev = *c;
+            b
+        })
+        .collect();
+
+    // `d[i][j]`: the best score with input[i] matched at word[j]
+    // `best[i][j]`: the best score of input[..=i] in word[..=j]
+    let mut d = vec![vec![f64::NEG_INFINITY; m]; n];
+    let mut best = vec![vec![f64::NEG_INFINITY; m]; n];
+    for i in 0..n {
+        let mut prev_score = f64::NEG_INFINITY;
+        let gap_score = if i == n - 1 {
+            SCORE_GAP_TRAILING
+        } else {
+            SCORE_GAP_INNER
+        };
+        for j in 0..m {
+            if input.match_char(i, word, j) {
+                let score = if i == 0 {
+                    j as f64 * SCORE_GAP_LEADING + bonuses[j]
+                } else if j > 0 {
+                    f64::max(
+                        best[i - 1][j - 1] + bonuses[j],
+                        d[i - 1][j - 1] + SCORE_MATCH_CONSECUTIVE,
+                    )
+                } else {
+                    f64::NEG_INFINITY
+                };
+                d[i][j] = score;
+                prev_score = f64::max(score, prev_score + gap_score);
+            } else {
+                prev_score += gap_score;
+            }
+            best[i][j] = prev_score;
+        }
+    }
+
+    // walk back through the matrices for the positions of the best score
+    let mut positions = vec![0; n];
+    let mut match_required = false;
+    let mut j = m;
+    for i in (0..n).rev() {
+        while j > 0 {
+            j -= 1;
+            if d[i][j] != f64::NEG_INFINITY && (match_required || d[i][j] == best[i][j]) {
+                match_required =
+                    i > 0 && j > 0 && best[i][j] == d[i - 1][j - 1] + SCORE_MATCH_CONSECUTIVE;
+                positions[i] = j;
+                break;
+            }
+        }
+    }
+    (best[n - 1][m - 1], positions)
+}
+
+/// `matcher::do_match` with the fzy scoring, the regions are in char indices.
+///
+/// The fzy score is mapped into `(1, 2]` so that a match still scores at least 1, and an exact
+/// match scores 2.
+pub fn do_match(input: &Chars, word: &Chars, class: &CharClass) -> (f64, Vec<MatchRegion>) {
+    if !has_match(input, word) {
+        return (0.0, Vec::new());
+    }
+    let (score, positions) = score(input, word);
+    let score = 1.0 + f64::exp(f64::min(score - input.len() as f64, 0.0));
+
+    let mut matches = Vec::<MatchRegion>::new();
+    let mut strict_counts = Vec::<usize>::new();
+    for (i, j) in positions.into_iter().enumerate() {
+        let strict = usize::from(input.chars[i] == word.chars[j]);
+        match matches.last_mut() {
+            Some(m) if m.word_match_end == j => {
+                m.input_match_end += 1;
+                m.word_match_end += 1;
+                *strict_counts.last_mut().unwrap() += strict;
+            }
+            _ => {
+                matches.push(MatchRegion {
+                    input_match_start: i,
+                    input_match_end: i + 1,
+                    word_match_start: j,
+                    word_match_end: j + 1,
+                    strict_ratio: 0.0,
+                    fuzzy: false,
+                    typo: false,
+                    index: (1..=j)
+                        .filter(|k| class.is_semantic_index(&word.chars, *k))
+                        .count(),
+                });
+                strict_counts.push(strict);
+            }
+        }
+    }
+    for (m, strict_count) in matches.iter_mut().zip(strict_counts) {
+        m.strict_ratio = strict_count as f64 / (m.input_match_end - m.input_match_start) as f64;
+    }
+    (score, matches)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fzy(input: &str, word: &str) -> (f64, Vec<usize>) {
+        score(&Chars::new(input), &Chars::new(word))
+    }
+
+    #[test]
+    fn scores() {
+        assert!(has_match(
+            &Chars::new("amor"),
+            &Chars::new("app/models/order")
+        ));
+        assert!(!has_match(&Chars::new("amor"), &Chars::new("app/models")));
+        assert_eq!(fzy("abc", "ABC").0, f64::INFINITY);
+
+        // consecutive matches win over word boundaries
+        assert!(fzy("abc", "abc_x").0 > fzy("abc", "a_b_c").0);
+        // boundaries win over matches in the middle of words
+        assert!(fzy("ab", "a_b").0 > fzy("ab", "axb").0);
+        assert!(fzy("am", "app/models").0 > fzy("am", "tram").0);
+        // shorter gaps win
+        assert!(fzy("ab", "a_xb").0 < fzy("ab", "a_b").0);
+        assert!(fzy("test", "tests").0 > fzy("test", "testing").0);
+        assert!(fzy("gsp", "getStaticProps").0 > fzy("gsp", "gasp").0);
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(fzy("amo", "app/models/foo").1, vec![0, 4, 5]);
+        // the best alignment, not the first one
+        assert_eq!(fzy("ab", "axxb_ab").1, vec![5, 6]);
+        assert_eq!(fzy("foo", "f/o/foo").1, vec![4, 5, 6]);
+        assert_eq!(fzy("gsp", "getStaticProps").1, vec![0, 3, 9]);
+    }
+
+    #[test]
+    fn regions() {
+        let none = CharClass::default();
+        let (score, matches) = do_match(&Chars::new("aXb"), &Chars::new("xx_axb"), &none);
+        assert!(score > 1.0 && score < 2.0);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].word_match_start, 3);
+        assert_eq!(matches[0].word_match_end, 6);
+        assert!((matches[0].strict_ratio - 2.0 / 3.0).abs() < 0.0001);
+        assert_eq!(matches[0].index, 2);
+
+        assert_eq!(do_match(&Chars::new("ab"), &Chars::new("AB"), &none).0, 2.0);
+        assert_eq!(do_match(&Chars::new("ab"), &Chars::new("ba"), &none).0, 0.0);
+    }
+}
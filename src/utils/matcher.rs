@@ -1,10 +1,247 @@
-use crate::utils::byte_char;
+use crate::utils::{char_class::CharClass, fzy, misc, path, unicode_char};
 use mlua::prelude::*;
+use serde::Deserialize;
 
 const WORD_BOUNDALY_ORDER_FACTOR: i32 = 10;
-const PREFIX_FACTOR: i32 = 8;
-const NOT_FUZZY_FACTOR: i32 = 6;
+const PREFIX_FACTOR: f64 = 8.0;
+const NOT_FUZZY_FACTOR: f64 = 6.0;
+const ACRONYM_FACTOR: f64 = 6.0;
+const BASENAME_FACTOR: f64 = 8.0;
 
+/// The `matching` options of nvim-cmp.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MatchingOption {
+    /// Never use the fuzzy pass
+    pub disallow_fuzzy_matching: bool,
+
+    /// The remaining input of the fuzzy pass must match a contiguous part of the word
+    pub disallow_fullfuzzy_matching: bool,
+
+    /// Only use the fuzzy pass for the remaining input of a prefix match
+    pub disallow_partial_fuzzy_matching: bool,
+
+    /// The input must match a single region of the word
+    pub disallow_partial_matching: bool,
+
+    /// The first char of the input must match the first char of the word
+    pub disallow_prefix_unmatching: bool,
+
+    /// Symbols of the input can only match in a prefix match
+    pub disallow_symbol_nonprefix_matching: bool,
+
+    /// Split the input on whitespace and match every term on its own, all of them have to match
+    pub multi_term: bool,
+
+    /// Compare the base letters of the chars with diacritics, so that `cafe` matches `café`
+    pub ignore_diacritics: bool,
+
+    /// When the input has an uppercase letter, uppercase letters of the input only match
+    /// uppercase letters
+    pub smart_case: bool,
+
+    /// Maximum number of typos (insertions, deletions, substitutions or transpositions) to
+    /// tolerate when nothing matches otherwise, 0 disables the typo-tolerant pass
+    pub max_typos: usize,
+
+    /// Also match the input against the initials of the word, see `Query::match_acronym`
+    pub acronym_matching: bool,
+}
+
+impl Default for MatchingOption {
+    fn default() -> Self {
+        Self {
+            disallow_fuzzy_matching: false,
+            disallow_fullfuzzy_matching: false,
+            disallow_partial_fuzzy_matching: true,
+            disallow_partial_matching: false,
+            disallow_prefix_unmatching: false,
+            disallow_symbol_nonprefix_matching: true,
+            multi_term: false,
+            ignore_diacritics: false,
+            smart_case: false,
+            max_typos: 0,
+            acronym_matching: true,
+        }
+    }
+}
+
+/// A Lua `matching` table applied on top of the configured `MatchingOption`: only the options
+/// it sets change.
+///
+/// cmp passes its own `matching` table on every completion, which only has the `disallow_*`
+/// options, so the other ones keep the values set with `matcher.configure`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct MatchingOverride {
+    pub disallow_fuzzy_matching: Option<bool>,
+    pub disallow_fullfuzzy_matching: Option<bool>,
+    pub disallow_partial_fuzzy_matching: Option<bool>,
+    pub disallow_partial_matching: Option<bool>,
+    pub disallow_prefix_unmatching: Option<bool>,
+    pub disallow_symbol_nonprefix_matching: Option<bool>,
+    pub multi_term: Option<bool>,
+    pub ignore_diacritics: Option<bool>,
+    pub smart_case: Option<bool>,
+    pub max_typos: Option<usize>,
+    pub acronym_matching: Option<bool>,
+}
+
+impl MatchingOverride {
+    pub fn apply(&self, option: &mut MatchingOption) {
+        macro_rules! set {
+            ($($field:ident),+) => {
+                $(
+                    if let Some(value) = self.$field {
+                        option.$field = value;
+                    }
+                )+
+            };
+        }
+        set!(
+            disallow_fuzzy_matching,
+            disallow_fullfuzzy_matching,
+            disallow_partial_fuzzy_matching,
+            disallow_partial_matching,
+            disallow_prefix_unmatching,
+            disallow_symbol_nonprefix_matching,
+            multi_term,
+            ignore_diacritics,
+            smart_case,
+            max_typos,
+            acronym_matching
+        );
+    }
+}
+
+/// How the input is matched against a word.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// `do_match`
+    #[default]
+    Word,
+
+    /// Path segment by path segment, see `utils::path`
+    Path,
+}
+
+/// The scorer behind `do_match`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    /// The word boundary heuristic of nvim-cmp
+    #[default]
+    Cmp,
+
+    /// The optimal alignment of fzy, see `utils::fzy`. Only `smart_case` of the matching options
+    /// applies.
+    Fzy,
+}
+
+/// Scoring weights and options of `do_match`, set from Lua with `matcher.configure`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MatcherConfig {
+    /// Bonus for a match that starts at the beginning of the word (or one of `words`)
+    pub prefix: f64,
+
+    /// Number of word boundaries over which earlier matches are preferred
+    pub boundary_order: i32,
+
+    /// Bonus for a match that does not need the fuzzy pass
+    pub not_fuzzy: f64,
+
+    /// Bonus for a match of the initials of the word (`gsp` -> `getStaticProps`)
+    pub acronym: f64,
+
+    /// Bonus of the path strategy for a match in the file name
+    pub basename: f64,
+
+    pub strategy: Strategy,
+
+    pub algorithm: Algorithm,
+
+    pub matching: MatchingOption,
+
+    /// The identifier chars of the filetype, for the word boundaries
+    #[serde(skip)]
+    pub char_class: CharClass,
+}
+
+impl Default for MatcherConfig {
+    fn default() -> Self {
+        Self {
+            prefix: PREFIX_FACTOR,
+            boundary_order: WORD_BOUNDALY_ORDER_FACTOR,
+            not_fuzzy: NOT_FUZZY_FACTOR,
+            acronym: ACRONYM_FACTOR,
+            basename: BASENAME_FACTOR,
+            strategy: Strategy::Word,
+            algorithm: Algorithm::Cmp,
+            matching: MatchingOption::default(),
+            char_class: CharClass::default(),
+        }
+    }
+}
+
+impl MatcherConfig {
+    /// The config set by `matcher.configure`, or the default one.
+    pub fn from_app_data(lua: &Lua) -> Self {
+        match lua.app_data_ref::<MatcherConfig>() {
+            Some(config) => config.clone(),
+            None => Self::default(),
+        }
+    }
+
+    /// Sets the config used by the matcher from the Lua table of `matcher.configure`.
+    pub fn configure(lua: &Lua, opts: LuaValue) -> LuaResult<()> {
+        let config: MatcherConfig = lua.from_value(opts)?;
+        config.validate().map_err(LuaError::RuntimeError)?;
+        lua.set_app_data(config);
+        Ok(())
+    }
+
+    /// Rejects the weights the scoring can't work with.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.boundary_order <= 0 {
+            return Err(format!(
+                "boundary_order must be positive, got {}",
+                self.boundary_order
+            ));
+        }
+        Ok(())
+    }
+
+    /// The multiplier of a region at the `order`-th word boundary: it decreases by
+    /// `1 / boundary_order` per boundary, and is 1 past `boundary_order` of them.
+    fn boundary_multiplier(&self, order: i32) -> f64 {
+        1.0 + std::cmp::max(0, self.boundary_order - order) as f64 / self.boundary_order as f64
+    }
+
+    /// `with_matching` with the `matching` table of the `opts` table of the Lua functions.
+    pub fn with_opts<'lua>(self, lua: &'lua Lua, opts: &LuaValue<'lua>) -> LuaResult<Self> {
+        match opts {
+            LuaValue::Table(opts) => self.with_matching(lua, opts.get("matching")?),
+            _ => Ok(self),
+        }
+    }
+
+    /// Overrides the matching options set in a Lua `matching` table, see `MatchingOverride`.
+    pub fn with_matching<'lua>(
+        mut self,
+        lua: &'lua Lua,
+        matching: LuaValue<'lua>,
+    ) -> LuaResult<Self> {
+        if matching != LuaValue::Nil {
+            let matching: MatchingOverride = lua.from_value(matching)?;
+            matching.apply(&mut self.matching);
+        }
+        Ok(self)
+    }
+}
+
+/// A matched region. The offsets are byte offsets into the input and the word so that Lua can
+/// slice them with `string.sub`, even though matching works on unicode scalar values.
 #[derive(Debug, Clone)]
 pub struct MatchRegion {
     pub input_match_start: usize,
@@ -13,6 +250,9 @@ pub struct MatchRegion {
     pub word_match_end: usize,
     pub strict_ratio: f64,
     pub fuzzy: bool,
+
+    /// The region only matches with typos, see `MatchingOption::max_typos`
+    pub typo: bool,
     pub index: usize,
 }
 impl MatchRegion {
@@ -26,9 +266,71 @@ impl MatchRegion {
         m_lua.set("index", m.index + 1)?;
         m_lua.set("strict_ratio", m.strict_ratio)?;
         m_lua.set("fuzzy", m.fuzzy)?;
+        m_lua.set("typo", m.typo)?;
+        Ok(m_lua)
+    }
+
+    /// `to_lua` with the `*_col` and `*_utf16` fields of `RegionPositions` as well.
+    pub fn to_lua_with_positions<'lua>(
+        &self,
+        lua: &'lua Lua,
+        input: &str,
+        word: &str,
+    ) -> LuaResult<LuaTable<'lua>> {
+        let m_lua = self.to_lua(lua)?;
+        let p = self.positions(input, word);
+        m_lua.set("input_match_start_col", p.input_col.0)?;
+        m_lua.set("input_match_end_col", p.input_col.1)?;
+        m_lua.set("word_match_start_col", p.word_col.0)?;
+        m_lua.set("word_match_end_col", p.word_col.1)?;
+        m_lua.set("input_match_start_utf16", p.input_utf16.0)?;
+        m_lua.set("input_match_end_utf16", p.input_utf16.1)?;
+        m_lua.set("word_match_start_utf16", p.word_utf16.0)?;
+        m_lua.set("word_match_end_utf16", p.word_utf16.1)?;
         Ok(m_lua)
     }
+
+    /// The region in display columns and UTF-16 code units of the input and the word it was
+    /// matched with.
+    pub fn positions(&self, input: &str, word: &str) -> RegionPositions {
+        let cols = |text: &str, start: usize, end: usize| {
+            (
+                misc::to_display_col(text, start) + 1,
+                misc::to_display_col(text, end),
+            )
+        };
+        let utf16 = |text: &str, start: usize, end: usize| {
+            (
+                misc::to_utf16_index(text, start) + 1,
+                misc::to_utf16_index(text, end),
+            )
+        };
+        RegionPositions {
+            input_col: cols(input, self.input_match_start, self.input_match_end),
+            word_col: cols(word, self.word_match_start, self.word_match_end),
+            input_utf16: utf16(input, self.input_match_start, self.input_match_end),
+            word_utf16: utf16(word, self.word_match_start, self.word_match_end),
+        }
+    }
+
+    /// Converts char indices into the byte offsets of the matched texts.
+    fn map_to_byte_offsets(&mut self, input_offsets: &[usize], word_offsets: &[usize]) {
+        self.input_match_start = input_offsets[self.input_match_start];
+        self.input_match_end = input_offsets[self.input_match_end];
+        self.word_match_start = word_offsets[self.word_match_start];
+        self.word_match_end = word_offsets[self.word_match_end];
+    }
+}
+/// `(start, end)` pairs in the same form as the byte offsets of `MatchRegion::to_lua`: the start
+/// is 1-based and the end is inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionPositions {
+    pub input_col: (usize, usize),
+    pub word_col: (usize, usize),
+    pub input_utf16: (usize, usize),
+    pub word_utf16: (usize, usize),
 }
+
 /// score
 ///
 /// ### The score
@@ -86,103 +388,1033 @@ impl MatchRegion {
 ///
 ///      * The `accept`'s `a` should not match to `candle`'s `a`
 ///
-pub fn do_match(input: &[u8], word: &[u8], words: &[&[u8]]) -> (f64, Vec<MatchRegion>) {
-    if input.is_empty() {
-        return (PREFIX_FACTOR as f64 + NOT_FUZZY_FACTOR as f64, Vec::new());
-    }
-    let mut matches = Vec::<MatchRegion>::new();
-    let mut input_start_index = 0;
-    let mut input_end_index = 0;
-    let mut word_index = 0;
-    let mut word_bound_index = 0;
-    while input_end_index < input.len() && word_index < word.len() {
-        let m = find_match_region(input, input_start_index, input_end_index, word, word_index);
-        if m.is_some() && input_end_index < m.as_ref().unwrap().input_match_end {
-            let mut m = m.unwrap();
-            m.index = word_bound_index;
-            input_start_index = m.input_match_start + 1;
-            input_end_index = m.input_match_end;
-            word_index = byte_char::get_next_semantic_index(word, m.word_match_end - 1);
-            matches.push(m);
+pub fn do_match(
+    input: &str,
+    word: &str,
+    words: &[&str],
+    config: &MatcherConfig,
+) -> (f64, Vec<MatchRegion>) {
+    Query::new(input, config).do_match(word, words)
+}
+
+/// The `{ score, matches }` table returned by `matcher.match`.
+///
+/// With the `(input, word)` that were matched, the regions have their `RegionPositions` too.
+pub fn matched_to_lua<'lua>(
+    lua: &'lua Lua,
+    matched: &(f64, Vec<MatchRegion>),
+    positions: Option<(&str, &str)>,
+) -> LuaResult<LuaTable<'lua>> {
+    let r = lua.create_table()?;
+    r.set(1, matched.0)?;
+    let matches = lua.create_table()?;
+    for (i, m) in matched.1.iter().enumerate() {
+        let m_lua = match positions {
+            Some((input, word)) => m.to_lua_with_positions(lua, input, word)?,
+            None => m.to_lua(lua)?,
+        };
+        matches.set(i + 1, m_lua)?;
+    }
+    r.set(2, matches)?;
+    Ok(r)
+}
+
+/// Whether `match_many` should return the matched regions, from its `opts` table.
+pub fn with_regions(opts: &LuaValue) -> LuaResult<bool> {
+    match opts {
+        LuaValue::Table(opts) => Ok(opts.get::<_, Option<bool>>("regions")?.unwrap_or(false)),
+        _ => Ok(false),
+    }
+}
+
+/// Whether `match` and `match_extended` should return the `RegionPositions` of the regions, from
+/// their `opts` table.
+pub fn with_positions(opts: &LuaValue) -> LuaResult<bool> {
+    match opts {
+        LuaValue::Table(opts) => Ok(opts.get::<_, Option<bool>>("positions")?.unwrap_or(false)),
+        _ => Ok(false),
+    }
+}
+
+pub fn results_to_lua<'lua>(
+    lua: &'lua Lua,
+    results: &[MatchResult],
+    with_regions: bool,
+) -> LuaResult<LuaTable<'lua>> {
+    let r = lua.create_table_with_capacity(results.len() as i32, 0)?;
+    for (i, result) in results.iter().enumerate() {
+        r.set(i + 1, result.to_lua(lua, with_regions)?)?;
+    }
+    Ok(r)
+}
+
+/// A word to match with `match_many`, along with the `words` used for the prefix bonus.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub word: String,
+    pub words: Vec<String>,
+}
+
+impl<'lua> FromLua<'lua> for Candidate {
+    fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
+        match lua_value {
+            // a `{ word, words }` record
+            LuaValue::Table(tbl) => Ok(Self {
+                word: tbl.get("word")?,
+                words: tbl
+                    .get::<_, Option<Vec<String>>>("words")?
+                    .unwrap_or_default(),
+            }),
+            _ => Ok(Self {
+                word: String::from_lua(lua_value, lua)?,
+                words: Vec::new(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchResult {
+    /// Index of the candidate in the list given to `match_many`
+    pub index: usize,
+    pub score: f64,
+    pub matches: Vec<MatchRegion>,
+}
+
+impl MatchResult {
+    pub fn to_lua<'lua>(&self, lua: &'lua Lua, with_regions: bool) -> LuaResult<LuaTable<'lua>> {
+        let r = lua.create_table()?;
+        r.set("index", self.index + 1)?;
+        r.set("score", self.score)?;
+        if with_regions {
+            let matches = lua.create_table()?;
+            for (i, m) in self.matches.iter().enumerate() {
+                matches.set(i + 1, m.to_lua(lua)?)?;
+            }
+            r.set("matches", matches)?;
+        }
+        Ok(r)
+    }
+}
+
+/// Matches all the candidates against the same input.
+///
+/// Only the matching candidates are returned, sorted by score. Candidates with the same score
+/// keep their order in the list.
+pub fn match_many(
+    input: &str,
+    candidates: &[Candidate],
+    config: &MatcherConfig,
+) -> Vec<MatchResult> {
+    Query::new(input, config).match_many(candidates)
+}
+
+/// Explains the score `do_match` gives to the word, see `Explanation`.
+pub fn explain(input: &str, word: &str, words: &[&str], config: &MatcherConfig) -> Explanation {
+    Query::new(input, config).explain(word, words)
+}
+
+/// How a region contributes to the score.
+#[derive(Debug, Clone)]
+pub struct RegionScore {
+    pub region: MatchRegion,
+
+    /// Number of input chars matched by the region and not by a previous one
+    pub base: f64,
+
+    /// `1 + strict_ratio`
+    pub strict_multiplier: f64,
+
+    /// Preference for the regions near the beginning of the word
+    pub boundary_multiplier: f64,
+
+    /// `base * strict_multiplier * boundary_multiplier`
+    pub score: f64,
+}
+
+impl RegionScore {
+    /// A region of a score that isn't a sum of per-region parts, with a `base` and a `score`
+    /// of 0.
+    fn unscored(region: MatchRegion) -> Self {
+        Self {
+            strict_multiplier: 1.0 + region.strict_ratio,
+            region,
+            base: 0.0,
+            boundary_multiplier: 1.0,
+            score: 0.0,
+        }
+    }
+
+    pub fn to_lua<'lua>(&self, lua: &'lua Lua) -> LuaResult<LuaTable<'lua>> {
+        let r = lua.create_table()?;
+        r.set("region", self.region.to_lua(lua)?)?;
+        r.set("base", self.base)?;
+        r.set("strict_multiplier", self.strict_multiplier)?;
+        r.set("boundary_multiplier", self.boundary_multiplier)?;
+        r.set("score", self.score)?;
+        Ok(r)
+    }
+}
+
+/// The breakdown of a `do_match` score, built by the same code.
+///
+/// `score` is `prefix_bonus + sum(regions.score) + not_fuzzy_bonus + acronym_bonus`, except for
+/// a full fuzzy match that scores 1. Only the word strategy with the cmp algorithm scores that
+/// way: with the typo pass, the fzy algorithm, the path strategy and multiple terms, `regions`
+/// are the matched regions with a `score` of 0 and only `score` is set.
+#[derive(Debug, Clone, Default)]
+pub struct Explanation {
+    pub prefix: bool,
+    pub prefix_bonus: f64,
+    pub regions: Vec<RegionScore>,
+
+    /// The region found by the fuzzy pass for the remaining input
+    pub fuzzy_tail: Option<MatchRegion>,
+    pub not_fuzzy_bonus: f64,
+
+    /// Set when the acronym pass scores better than the regular one
+    pub acronym_bonus: f64,
+    pub typo: bool,
+    pub score: f64,
+
+    /// Why the word doesn't match
+    pub rejected: Option<&'static str>,
+}
+
+impl Explanation {
+    pub fn to_lua<'lua>(&self, lua: &'lua Lua) -> LuaResult<LuaTable<'lua>> {
+        let r = lua.create_table()?;
+        r.set("prefix", self.prefix)?;
+        r.set("prefix_bonus", self.prefix_bonus)?;
+        let regions = lua.create_table_with_capacity(self.regions.len() as i32, 0)?;
+        for (i, region) in self.regions.iter().enumerate() {
+            regions.set(i + 1, region.to_lua(lua)?)?;
+        }
+        r.set("regions", regions)?;
+        if let Some(fuzzy_tail) = &self.fuzzy_tail {
+            r.set("fuzzy_tail", fuzzy_tail.to_lua(lua)?)?;
+        }
+        r.set("not_fuzzy_bonus", self.not_fuzzy_bonus)?;
+        r.set("acronym_bonus", self.acronym_bonus)?;
+        r.set("typo", self.typo)?;
+        r.set("score", self.score)?;
+        r.set("rejected", self.rejected)?;
+        Ok(r)
+    }
+
+    fn set_unscored(explanation: Option<&mut Self>, matches: &[MatchRegion]) {
+        if let Some(e) = explanation {
+            e.regions = matches.iter().cloned().map(RegionScore::unscored).collect();
+        }
+    }
+
+    fn reject(explanation: Option<&mut Self>, reason: &'static str) -> (f64, Vec<MatchRegion>) {
+        if let Some(e) = explanation {
+            e.rejected = Some(reason);
+        }
+        (0.0, Vec::new())
+    }
+}
+
+/// Sorts the regions by their position in the word and merges the overlapping ones.
+pub fn merge_regions(mut regions: Vec<MatchRegion>) -> Vec<MatchRegion> {
+    regions.sort_by_key(|m| (m.word_match_start, m.word_match_end));
+    let mut merged: Vec<MatchRegion> = Vec::with_capacity(regions.len());
+    for m in regions {
+        match merged.last_mut() {
+            Some(last) if m.word_match_start <= last.word_match_end => {
+                last.word_match_end = std::cmp::max(last.word_match_end, m.word_match_end);
+                last.input_match_start = std::cmp::min(last.input_match_start, m.input_match_start);
+                last.input_match_end = std::cmp::max(last.input_match_end, m.input_match_end);
+                last.strict_ratio = last.strict_ratio.min(m.strict_ratio);
+                last.fuzzy = last.fuzzy || m.fuzzy;
+                last.typo = last.typo || m.typo;
+            }
+            _ => merged.push(m),
+        }
+    }
+    merged
+}
+
+/// A text split into unicode scalar values.
+#[derive(Debug, Clone)]
+pub struct Chars {
+    pub chars: Vec<char>,
+
+    /// `chars` after case folding
+    pub folded: Vec<char>,
+
+    /// Byte offset of every char, followed by the byte length of the text
+    pub offsets: Vec<usize>,
+
+    /// Uppercase chars only match uppercase chars, see `MatchingOption::smart_case`
+    pub smart_case: bool,
+}
+
+impl Chars {
+    pub fn new(text: &str) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+        Self {
+            folded: chars.iter().map(|c| unicode_char::fold_case(*c)).collect(),
+            offsets: text
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain(std::iter::once(text.len()))
+                .collect(),
+            chars,
+            smart_case: false,
+        }
+    }
+
+    /// Drops the diacritics of the folded chars, see `unicode_char::fold_diacritics`.
+    pub fn fold_diacritics(mut self) -> Self {
+        for c in &mut self.folded {
+            *c = unicode_char::fold_diacritics(*c);
+        }
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    /// Whether `self[index]` and `other[other_index]` match, ignoring case unless one of them is
+    /// in smart case.
+    pub fn match_char(&self, index: usize, other: &Chars, other_index: usize) -> bool {
+        self.folded[index] == other.folded[other_index]
+            && self.match_case(index, other.chars[other_index])
+            && other.match_case(other_index, self.chars[index])
+    }
+
+    /// Whether `self[index]` can match `c` in smart case.
+    pub fn match_case(&self, index: usize, c: char) -> bool {
+        !self.smart_case || !unicode_char::is_upper(self.chars[index]) || unicode_char::is_upper(c)
+    }
+}
+
+/// The set of chars in a text as a 64-bit mask, to reject words that can't match cheaply.
+///
+/// Letters and digits get their own bit (after case and diacritics folding), any other char
+/// shares one of the remaining bits. A word can only match the input if its bag contains the
+/// input's bag, or misses at most as many bits as the typos allowed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CharBag(u64);
+
+impl CharBag {
+    pub fn new(text: &str) -> Self {
+        let mut bag = 0;
+        for c in text.chars() {
+            bag |= Self::bit(unicode_char::fold_diacritics(unicode_char::fold_case(c)));
+        }
+        Self(bag)
+    }
+
+    fn bit(c: char) -> u64 {
+        let index = match c {
+            'a'..='z' => c as u32 - 'a' as u32,
+            '0'..='9' => 26 + c as u32 - '0' as u32,
+            _ => 36 + c as u32 % 28,
+        };
+        1 << index
+    }
+
+    /// The number of bits of `other` that are not in the bag, a lower bound of the number of
+    /// chars of `other` missing from the text.
+    pub fn missing(&self, other: &CharBag) -> usize {
+        (other.0 & !self.0).count_ones() as usize
+    }
+}
+
+/// An input compiled once to be matched against many words.
+#[derive(Debug, Clone)]
+pub struct Query {
+    input: Chars,
+
+    char_bag: CharBag,
+
+    /// The queries of the path segments with their byte offsets, with the path strategy
+    path_segments: Option<Vec<(usize, Query)>>,
+
+    /// The queries of the terms with their byte offsets, with `MatchingOption::multi_term`
+    terms: Option<Vec<(usize, Query)>>,
+
+    config: MatcherConfig,
+}
+
+impl Query {
+    pub fn new(input: &str, config: &MatcherConfig) -> Self {
+        let char_bag = CharBag::new(input);
+        let path_segments = match config.strategy {
+            Strategy::Word => None,
+            Strategy::Path => Some(path::compile(input, config)),
+        };
+        let terms = if config.strategy == Strategy::Word
+            && config.matching.multi_term
+            && input.chars().any(unicode_char::is_white)
+        {
+            let mut config = config.clone();
+            config.matching.multi_term = false;
+            Some(
+                split_terms(input)
+                    .into_iter()
+                    .map(|(offset, term)| (offset, Query::new(term, &config)))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        let mut input = Chars::new(input);
+        if config.matching.ignore_diacritics {
+            input = input.fold_diacritics();
+        }
+        input.smart_case =
+            config.matching.smart_case && input.chars.iter().any(|c| unicode_char::is_upper(*c));
+        Self {
+            input,
+            char_bag,
+            path_segments,
+            terms,
+            config: config.clone(),
+        }
+    }
+
+    /// Semantic indices of the input, as byte offsets. Matching doesn't need them, so they are
+    /// only computed on demand.
+    pub fn boundaries(&self) -> Vec<usize> {
+        (0..self.input.len())
+            .filter(|i| {
+                self.config
+                    .char_class
+                    .is_semantic_index(&self.input.chars, *i)
+            })
+            .map(|i| self.input.offsets[i])
+            .collect()
+    }
+
+    pub fn do_match(&self, word: &str, words: &[&str]) -> (f64, Vec<MatchRegion>) {
+        self.match_word(word, words, true, None)
+    }
+
+    /// `do_match` without the `CharBag` check, only useful to measure the latter.
+    pub fn do_match_without_prefilter(
+        &self,
+        word: &str,
+        words: &[&str],
+    ) -> (f64, Vec<MatchRegion>) {
+        self.match_word(word, words, false, None)
+    }
+
+    /// See `Explanation`.
+    pub fn explain(&self, word: &str, words: &[&str]) -> Explanation {
+        let mut explanation = Explanation::default();
+        explanation.score = self.match_word(word, words, true, Some(&mut explanation)).0;
+        explanation
+    }
+
+    fn match_word(
+        &self,
+        word: &str,
+        words: &[&str],
+        prefilter: bool,
+        mut explanation: Option<&mut Explanation>,
+    ) -> (f64, Vec<MatchRegion>) {
+        let empty = match (&self.path_segments, &self.terms) {
+            (Some(parts), _) | (None, Some(parts)) => parts.is_empty(),
+            (None, None) => self.input.is_empty(),
+        };
+        if empty {
+            if let Some(e) = explanation {
+                e.prefix = true;
+                e.prefix_bonus = self.config.prefix;
+                e.not_fuzzy_bonus = self.config.not_fuzzy;
+            }
+            return (self.config.prefix + self.config.not_fuzzy, Vec::new());
+        }
+        if let Some(segments) = &self.path_segments {
+            let matched = path::match_segments(segments, word, &self.config);
+            if matched.0 == 0.0 {
+                return Explanation::reject(explanation, "path unmatched");
+            }
+            Explanation::set_unscored(explanation, &matched.1);
+            return matched;
+        }
+        if let Some(terms) = &self.terms {
+            let mut score = 0.0;
+            let mut matches = Vec::new();
+            for (offset, query) in terms {
+                let (s, m) = query.do_match(word, words);
+                if s == 0.0 {
+                    return Explanation::reject(explanation, "term unmatched");
+                }
+                score += s;
+                matches.extend(m.into_iter().map(|mut m| {
+                    m.input_match_start += offset;
+                    m.input_match_end += offset;
+                    m
+                }));
+            }
+            let matches = merge_regions(matches);
+            Explanation::set_unscored(explanation, &matches);
+            return (score, matches);
+        }
+        // every char of the input has to be matched somewhere in the word, and every missing
+        // char takes at least a typo
+        let missing = if prefilter {
+            CharBag::new(word).missing(&self.char_bag)
         } else {
-            word_index = byte_char::get_next_semantic_index(word, word_index);
+            0
+        };
+        if missing > 0 && (missing > self.max_typos() || self.config.algorithm == Algorithm::Fzy) {
+            return Explanation::reject(explanation, "missing chars");
         }
-        word_bound_index += 1;
+        let mut word = Chars::new(word);
+        if self.config.matching.ignore_diacritics {
+            word = word.fold_diacritics();
+        }
+        if self.config.algorithm == Algorithm::Fzy {
+            let (score, mut matches) = fzy::do_match(&self.input, &word, &self.config.char_class);
+            if score == 0.0 {
+                return Explanation::reject(explanation, "no match");
+            }
+            for m in &mut matches {
+                m.map_to_byte_offsets(&self.input.offsets, &word.offsets);
+            }
+            Explanation::set_unscored(explanation, &matches);
+            return (score, matches);
+        }
+        // only the typo pass can match when chars are missing
+        let (mut score, mut matches) = if missing == 0 {
+            self.match_chars(&word, words, explanation.as_deref_mut())
+        } else {
+            (0.0, Vec::new())
+        };
+        if let Some((s, m)) = self.match_acronym(&word, None).filter(|_| missing == 0) {
+            if s > score {
+                score = s;
+                matches = m;
+                if let Some(e) = explanation.as_deref_mut() {
+                    *e = Explanation::default();
+                    self.match_acronym(&word, Some(e));
+                }
+            }
+        }
+        if score == 0.0 && self.config.matching.max_typos > 0 {
+            if let Some((s, m)) = self.match_typo(&word) {
+                if let Some(e) = explanation.as_deref_mut() {
+                    *e = Explanation {
+                        regions: vec![RegionScore::unscored(m.clone())],
+                        typo: true,
+                        ..Explanation::default()
+                    };
+                }
+                score = s;
+                matches = vec![m];
+            }
+        }
+        if score == 0.0 && missing > 0 {
+            return Explanation::reject(explanation, "missing chars");
+        }
+        for m in &mut matches {
+            m.map_to_byte_offsets(&self.input.offsets, &word.offsets);
+        }
+        if let Some(e) = explanation {
+            for r in &mut e.regions {
+                r.region
+                    .map_to_byte_offsets(&self.input.offsets, &word.offsets);
+            }
+            if let Some(m) = &mut e.fuzzy_tail {
+                m.map_to_byte_offsets(&self.input.offsets, &word.offsets);
+            }
+        }
+        (score, matches)
+    }
+
+    /// The acronym pass.
+    ///
+    /// Every input char has to match an initial of the word in order, possibly skipping some.
+    /// The initials are the first char of each word boundary and the uppercase run that may
+    /// follow it, e.g. `HTMLIE` for `HTMLInputElement`. The returned regions are in char indices.
+    ///
+    /// It only runs for inputs of at least 3 chars, all alphanumeric, and only matches across at
+    /// least 2 regions. So unlike the other passes, an input can match a word that a prefix of
+    /// the input doesn't match, e.g. `tmle` and `HTMLInputElement` but not `tml`.
+    fn match_acronym(
+        &self,
+        word: &Chars,
+        explanation: Option<&mut Explanation>,
+    ) -> Option<(f64, Vec<MatchRegion>)> {
+        let config = &self.config;
+        let option = &config.matching;
+        // two chars are too ambiguous to be an acronym
+        if self.input.len() < 3 || !self.may_match_acronym() {
+            return None;
+        }
+        let (mut matches, strict_counts) = self.match_initials(word)?;
+        // a single region is a plain prefix or substring match, which `match_chars` handles
+        if matches.len() < 2 {
+            return None;
+        }
+        let prefix = matches[0].word_match_start == 0;
+        if option.disallow_prefix_unmatching && !prefix {
+            return None;
+        }
+
+        let mut score = if prefix { config.prefix } else { 0.0 };
+        let mut region_scores = Vec::with_capacity(matches.len());
+        for (m, strict_count) in matches.iter_mut().zip(strict_counts) {
+            let base = (m.input_match_end - m.input_match_start) as f64;
+            m.strict_ratio = strict_count as f64 / base;
+            let strict_multiplier = 1.0 + m.strict_ratio;
+            let boundary_multiplier = config.boundary_multiplier(m.index as i32 + 1);
+            let s = base * strict_multiplier * boundary_multiplier;
+            score += s;
+            region_scores.push(RegionScore {
+                region: m.clone(),
+                base,
+                strict_multiplier,
+                boundary_multiplier,
+                score: s,
+            });
+        }
+        if let Some(e) = explanation {
+            e.prefix = prefix;
+            e.prefix_bonus = if prefix { config.prefix } else { 0.0 };
+            e.regions = region_scores;
+            e.acronym_bonus = config.acronym;
+        }
+        Some((score + config.acronym, matches))
+    }
+
+    /// Whether the options and the chars of the input allow the acronym pass.
+    fn may_match_acronym(&self) -> bool {
+        let option = &self.config.matching;
+        option.acronym_matching
+            && !option.disallow_partial_matching
+            && self.input.chars.iter().all(|c| unicode_char::is_alnum(*c))
+    }
+
+    /// Matches every input char to an initial of the word in order, see `match_acronym`.
+    ///
+    /// Returns the regions and the number of strictly matched chars of each of them.
+    fn match_initials(&self, word: &Chars) -> Option<(Vec<MatchRegion>, Vec<usize>)> {
+        let input = &self.input;
+        let config = &self.config;
+        let mut matches = Vec::<MatchRegion>::new();
+        let mut strict_counts = Vec::<usize>::new();
+        let mut input_index = 0;
+        let mut boundary = 0;
+        let mut initial_index = 0;
+        while boundary < word.len() && input_index < input.len() {
+            let next_boundary = config
+                .char_class
+                .get_next_semantic_index(&word.chars, boundary);
+            for word_index in boundary..next_boundary {
+                let c = word.chars[word_index];
+                let initial = if word_index == boundary {
+                    unicode_char::is_alnum(c)
+                } else {
+                    unicode_char::is_upper(c) && unicode_char::is_upper(word.chars[word_index - 1])
+                };
+                if !initial || input_index == input.len() {
+                    break;
+                }
+                if input.match_char(input_index, word, word_index) {
+                    let strict = usize::from(input.chars[input_index] == c);
+                    match matches.last_mut() {
+                        Some(m) if m.word_match_end == word_index => {
+                            m.input_match_end += 1;
+                            m.word_match_end += 1;
+                            *strict_counts.last_mut().unwrap() += strict;
+                        }
+                        _ => {
+                            matches.push(MatchRegion {
+                                input_match_start: input_index,
+                                input_match_end: input_index + 1,
+                                word_match_start: word_index,
+                                word_match_end: word_index + 1,
+                                strict_ratio: 0.0,
+                                fuzzy: false,
+                                typo: false,
+                                index: initial_index,
+                            });
+                            strict_counts.push(strict);
+                        }
+                    }
+                    input_index += 1;
+                }
+                initial_index += 1;
+            }
+            boundary = next_boundary;
+        }
+        if input_index < input.len() {
+            return None;
+        }
+        Some((matches, strict_counts))
+    }
+
+    /// Whether a longer input that starts with this one may match the word although this one
+    /// doesn't, i.e. whether the input matches initials of the word without the length and
+    /// region thresholds of `match_acronym`. The other passes never match a word that a prefix
+    /// of the input doesn't match.
+    ///
+    /// Always true with path segments or multiple terms, whose parts may change as the input
+    /// grows.
+    pub fn may_match_longer(&self, word: &str) -> bool {
+        if self.path_segments.is_some() || self.terms.is_some() {
+            return true;
+        }
+        if self.config.algorithm == Algorithm::Fzy || !self.may_match_acronym() {
+            return false;
+        }
+        let mut word = Chars::new(word);
+        if self.config.matching.ignore_diacritics {
+            word = word.fold_diacritics();
+        }
+        self.match_initials(&word).is_some()
+    }
+
+    /// The typo-tolerant pass.
+    ///
+    /// The input is compared to the beginning of the word and of every word boundary with the
+    /// optimal string alignment distance. The score is always below 1, i.e. below the score of
+    /// any match without typos.
+    fn match_typo(&self, word: &Chars) -> Option<(f64, MatchRegion)> {
+        let max_typos = self.max_typos();
+        if max_typos == 0 {
+            return None;
+        }
+        let mut rows = Vec::new();
+        let mut best: Option<(usize, usize, usize)> = None;
+        for start in 0..word.len() {
+            if !self.config.char_class.is_semantic_index(&word.chars, start)
+                || !unicode_char::is_alnum(word.chars[start])
+            {
+                continue;
+            }
+            if let Some((distance, len)) = prefix_distance(
+                &self.input.folded,
+                &word.folded[start..],
+                max_typos,
+                &mut rows,
+            ) {
+                if best.is_none_or(|(d, _, _)| distance < d) {
+                    best = Some((distance, start, len));
+                }
+            }
+        }
+        best.map(|(distance, start, len)| {
+            let penalty = if start == 0 { 0.0 } else { 0.5 };
+            (
+                1.0 / (1.0 + distance as f64 + penalty),
+                MatchRegion {
+                    input_match_start: 0,
+                    input_match_end: self.input.len(),
+                    word_match_start: start,
+                    word_match_end: start + len,
+                    strict_ratio: 0.0,
+                    fuzzy: false,
+                    typo: true,
+                    index: 0,
+                },
+            )
+        })
     }
 
-    if matches.is_empty() {
-        return (0.0, Vec::new());
-    }
+    /// The number of typos the typo pass tolerates: a typo every 3 chars so that short inputs
+    /// don't match everything.
+    fn max_typos(&self) -> usize {
+        std::cmp::min(self.config.matching.max_typos, self.input.len() / 3)
+    }
+
+    /// See `match_many`.
+    pub fn match_many(&self, candidates: &[Candidate]) -> Vec<MatchResult> {
+        let mut results: Vec<MatchResult> = candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(index, candidate)| {
+                let words: Vec<&str> = candidate.words.iter().map(|w| w.as_str()).collect();
+                let (score, matches) = self.do_match(&candidate.word, &words);
+                if score > 0.0 {
+                    Some(MatchResult {
+                        index,
+                        score,
+                        matches,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results
+    }
+
+    /// The actual `do_match` algorithm, working on unicode scalar values.
+    ///
+    /// The returned regions are in char indices, and so are the ones of the explanation.
+    fn match_chars(
+        &self,
+        word: &Chars,
+        words: &[&str],
+        mut explanation: Option<&mut Explanation>,
+    ) -> (f64, Vec<MatchRegion>) {
+        let input = &self.input;
+        let config = &self.config;
+        let option = &config.matching;
+        if option.disallow_prefix_unmatching && (word.is_empty() || !input.match_char(0, word, 0)) {
+            return Explanation::reject(explanation, "prefix unmatching");
+        }
+
+        let mut matches = Vec::<MatchRegion>::new();
+        let mut input_start_index = 0;
+        let mut input_end_index = 0;
+        let mut word_index = 0;
+        let mut word_bound_index = 0;
+        while input_end_index < input.len() && word_index < word.len() {
+            match find_match_region(input, input_start_index, input_end_index, word, word_index) {
+                Some(mut m) if input_end_index < m.input_match_end => {
+                    m.index = word_bound_index;
+                    input_start_index = m.input_match_start + 1;
+                    input_end_index = m.input_match_end;
+                    word_index = config
+                        .char_class
+                        .get_next_semantic_index(&word.chars, m.word_match_end - 1);
+                    matches.push(m);
+                }
+                _ => {
+                    word_index = config
+                        .char_class
+                        .get_next_semantic_index(&word.chars, word_index);
+                }
+            }
+            word_bound_index += 1;
+        }
+
+        if option.disallow_partial_matching && matches.len() > 1 {
+            return Explanation::reject(explanation, "partial matching");
+        }
+
+        if matches.is_empty() {
+            if !option.disallow_fuzzy_matching
+                && !option.disallow_prefix_unmatching
+                && !option.disallow_partial_fuzzy_matching
+                && fuzzy(input, word, &mut matches, config)
+            {
+                if let Some(e) = explanation {
+                    e.fuzzy_tail = matches.last().cloned();
+                }
+                return (1.0, matches);
+            }
+            return Explanation::reject(explanation, "no match");
+        }
 
-    // Add prefix bonus
-    let mut prefix = false;
-    if matches[0].input_match_start == 0 && matches[0].word_match_start == 0 {
-        prefix = true
-    } else {
-        for w in words {
-            prefix = true;
-            let mut o = 0;
-            for i in matches[0].input_match_start..matches[0].input_match_end {
-                if !byte_char::match_char(w[o], input[i]) {
-                    prefix = false;
+        // Add prefix bonus
+        let mut prefix = false;
+        if matches[0].input_match_start == 0 && matches[0].word_match_start == 0 {
+            prefix = true
+        } else {
+            for w in words {
+                let mut w_chars = w.chars();
+                prefix =
+                    (matches[0].input_match_start..matches[0].input_match_end).all(
+                        |i| match w_chars.next() {
+                            Some(w_c) => {
+                                unicode_char::match_char(w_c, input.chars[i])
+                                    && input.match_case(i, w_c)
+                            }
+                            None => false,
+                        },
+                    );
+                if prefix {
                     break;
                 }
-                o = o + 1;
             }
-            if prefix {
-                break;
+        }
+
+        if option.disallow_symbol_nonprefix_matching && !prefix {
+            let symbol_match = matches.iter().any(|m| {
+                input.chars[m.input_match_start..m.input_match_end]
+                    .iter()
+                    .any(|c| config.char_class.is_symbol(*c))
+            });
+            if symbol_match {
+                return Explanation::reject(explanation, "symbol nonprefix matching");
+            }
+        }
+
+        // Compute prefix match score
+        let mut score = if prefix { config.prefix } else { 0.0 };
+        if let Some(e) = explanation.as_deref_mut() {
+            e.prefix = prefix;
+            e.prefix_bonus = score;
+        }
+        let offset = if prefix { matches[0].index as i32 } else { 0 };
+        let mut idx = 0;
+        for m in &matches {
+            let mut s: f64 = 0.0;
+            for i in std::cmp::max(idx, m.input_match_start)..m.input_match_end {
+                s += 1.0;
+                idx = i;
+            }
+            idx += 1;
+            if s > 0.0 {
+                let base = s;
+                let strict_multiplier = 1.0 + m.strict_ratio;
+                let boundary_multiplier = config.boundary_multiplier(m.index as i32 - offset + 1);
+                s *= strict_multiplier;
+                s *= boundary_multiplier;
+                score += s;
+                if let Some(e) = explanation.as_deref_mut() {
+                    e.regions.push(RegionScore {
+                        region: m.clone(),
+                        base,
+                        strict_multiplier,
+                        boundary_multiplier,
+                        score: s,
+                    });
+                }
             }
         }
-    }
 
-    // Compute prefix match score
-    let mut score = if prefix { PREFIX_FACTOR } else { 0 } as f64;
-    let offset = if prefix { matches[0].index as i32 } else { 0 };
-    let mut idx = 0;
-    for m in &matches {
-        let mut s: f64 = 0.0;
-        for i in std::cmp::max(idx, m.input_match_start)..m.input_match_end {
-            s = s + 1.0;
-            idx = i;
+        // Check remaining input as fuzzy
+        if matches.last().unwrap().input_match_end < input.len() {
+            if !option.disallow_fuzzy_matching
+                && (prefix || !option.disallow_partial_fuzzy_matching)
+                && fuzzy(input, word, &mut matches, config)
+            {
+                if let Some(e) = explanation {
+                    e.fuzzy_tail = matches.last().cloned();
+                }
+                return (score, matches);
+            }
+            return Explanation::reject(explanation, "fuzzy tail unmatched");
         }
-        idx = idx + 1;
-        if s > 0.0 {
-            s = s as f64 * (1.0 + m.strict_ratio);
-            s = s as f64
-                * (1.0
-                    + std::cmp::max(
-                        0,
-                        WORD_BOUNDALY_ORDER_FACTOR - (m.index as i32 - offset as i32 + 1),
-                    ) as f64
-                        / WORD_BOUNDALY_ORDER_FACTOR as f64);
-            score = score + s
+
+        if let Some(e) = explanation {
+            e.not_fuzzy_bonus = config.not_fuzzy;
         }
+        (score + config.not_fuzzy, matches)
     }
+}
 
-    // Check remaining input as fuzzy
-    if matches.last().unwrap().input_match_end < input.len() {
-        if prefix && fuzzy(input, word, &mut matches) {
-            return (score, matches);
+impl LuaUserData for Query {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "match",
+            |lua, this, (word, words): (String, Option<Vec<String>>)| {
+                let words = words.unwrap_or_default();
+                let words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+                matched_to_lua(lua, &this.do_match(&word, &words), None)
+            },
+        );
+        methods.add_method(
+            "explain",
+            |lua, this, (word, words): (String, Option<Vec<String>>)| {
+                let words = words.unwrap_or_default();
+                let words: Vec<&str> = words.iter().map(|w| w.as_str()).collect();
+                this.explain(&word, &words).to_lua(lua)
+            },
+        );
+        methods.add_method(
+            "match_many",
+            |lua, this, (candidates, opts): (Vec<Candidate>, LuaValue)| {
+                results_to_lua(lua, &this.match_many(&candidates), with_regions(&opts)?)
+            },
+        );
+        methods.add_method("boundaries", |_, this, ()| {
+            Ok(this
+                .boundaries()
+                .iter()
+                .map(|b| b + 1)
+                .collect::<Vec<usize>>())
+        });
+    }
+}
+
+/// Splits the input into its whitespace separated terms, with their byte offsets.
+pub fn split_terms(input: &str) -> Vec<(usize, &str)> {
+    let mut terms = Vec::new();
+    let mut start = None;
+    for (i, c) in input
+        .char_indices()
+        .chain(std::iter::once((input.len(), ' ')))
+    {
+        match (start, unicode_char::is_white(c)) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                terms.push((s, &input[s..i]));
+                start = None;
+            }
+            _ => {}
         }
-        return (0.0, Vec::new());
     }
+    terms
+}
 
-    return (score + NOT_FUZZY_FACTOR as f64, matches);
+/// The smallest optimal string alignment distance (Damerau-Levenshtein without editing a
+/// substring twice) between `input` and a prefix of `text`, if it is at most `max_distance`.
+///
+/// Returns the distance and the length of the prefix. `rows` is the buffer of the distance
+/// matrix, reused between the calls.
+fn prefix_distance(
+    input: &[char],
+    text: &[char],
+    max_distance: usize,
+    rows: &mut Vec<usize>,
+) -> Option<(usize, usize)> {
+    let n = input.len();
+    let m = std::cmp::min(text.len(), n + max_distance);
+    if m + max_distance < n {
+        return None;
+    }
+    let width = m + 1;
+    rows.clear();
+    rows.resize((n + 1) * width, 0);
+    let at = |i: usize, j: usize| i * width + j;
+    for i in 0..=n {
+        rows[at(i, 0)] = i;
+    }
+    for j in 0..=m {
+        rows[at(0, j)] = j;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            let cost = if input[i - 1] == text[j - 1] { 0 } else { 1 };
+            let mut d = std::cmp::min(
+                std::cmp::min(rows[at(i - 1, j)] + 1, rows[at(i, j - 1)] + 1),
+                rows[at(i - 1, j - 1)] + cost,
+            );
+            if i > 1 && j > 1 && input[i - 1] == text[j - 2] && input[i - 2] == text[j - 1] {
+                d = std::cmp::min(d, rows[at(i - 2, j - 2)] + 1);
+            }
+            rows[at(i, j)] = d;
+        }
+    }
+    // prefer the prefix with the same length as the input on ties
+    (n.saturating_sub(max_distance)..=m)
+        .map(|j| (rows[at(n, j)], j.abs_diff(n), j))
+        .min()
+        .filter(|(distance, _, _)| *distance <= max_distance)
+        .map(|(distance, _, j)| (distance, j))
 }
 
-pub fn find_match_region<'a>(
-    input: &'a [u8],
+pub fn find_match_region(
+    input: &Chars,
     input_start_index: usize,
     mut input_end_index: usize,
-    word: &[u8],
+    word: &Chars,
     word_index: usize,
 ) -> Option<MatchRegion> {
     // determine input position (woroff -> word_offset)
     while input_start_index < input_end_index {
-        if byte_char::match_char(input[input_end_index], word[word_index]) {
+        if input.match_char(input_end_index, word, word_index) {
             break;
         }
-        input_end_index = input_end_index - 1;
+        input_end_index -= 1;
     }
     if input_end_index < input_start_index {
         return None;
@@ -193,32 +1425,22 @@ pub fn find_match_region<'a>(
     let mut strict_count = 0;
     let mut match_count = 0;
     while input_index < input.len() && word_index + word_offset < word.len() {
-        let c1 = input[input_index];
-        let c2 = word[word_index + word_offset];
-        if byte_char::match_char(c1, c2) {
+        if input.match_char(input_index, word, word_index + word_offset) {
             if input_match_start == -1 {
                 input_match_start = input_index as i32;
             }
-            strict_count += if c1 == c2 { 1 } else { 0 };
+            strict_count += if input.chars[input_index] == word.chars[word_index + word_offset] {
+                1
+            } else {
+                0
+            };
             match_count += 1;
             word_offset += 1;
         } else {
             // match end (partial region)
-            if input_match_start != -1 {
-                return Some(MatchRegion {
-                    input_match_start: input_match_start as usize,
-                    input_match_end: input_index,
-                    word_match_start: word_index,
-                    word_match_end: word_index + word_offset,
-                    strict_ratio: strict_count as f64 / match_count as f64,
-                    fuzzy: false,
-                    index: 0,
-                });
-            } else {
-                return None;
-            }
+            break;
         }
-        input_index = input_index + 1;
+        input_index += 1;
     }
     if input_match_start != -1 {
         return Some(MatchRegion {
@@ -228,76 +1450,87 @@ pub fn find_match_region<'a>(
             word_match_end: word_index + word_offset,
             strict_ratio: strict_count as f64 / match_count as f64,
             fuzzy: false,
+            typo: false,
             index: 0,
         });
     }
-    return None;
+    None
 }
 
-pub fn fuzzy(input: &[u8], word: &[u8], matches: &mut Vec<MatchRegion>) -> bool {
-    if let Some(last_match) = matches.last() {
-        let mut input_index = last_match.input_match_end;
-        for i in 0..matches.len() - 1 {
-            let curr_match = &matches[i];
-            let next_match = &matches[i + 1];
-            let mut word_offset = 0;
-            let mut word_index =
-                byte_char::get_next_semantic_index(word, curr_match.word_match_end - 1);
-            while word_offset + word_index < next_match.word_match_start
-                && input_index < input.len()
-            {
-                if byte_char::match_char(word[word_index + word_offset], input[input_index]) {
-                    input_index = input_index + 1;
-                    word_offset = word_offset + 1;
-                } else {
-                    word_index = byte_char::get_next_semantic_index(word, word_index + word_offset);
-                    word_offset = 0;
-                }
-            }
-        }
-        let last_input_index = input_index;
-        let mut matched = false;
+pub fn fuzzy(
+    input: &Chars,
+    word: &Chars,
+    matches: &mut Vec<MatchRegion>,
+    config: &MatcherConfig,
+) -> bool {
+    let class = &config.char_class;
+    let (mut input_index, word_index) = match matches.last() {
+        Some(last_match) => (last_match.input_match_end, last_match.word_match_end),
+        None => (0, 0),
+    };
+    for pair in matches.windows(2) {
+        let curr_match = &pair[0];
+        let next_match = &pair[1];
         let mut word_offset = 0;
-        let word_index = last_match.word_match_end;
-        let mut input_match_start: i32 = -1;
-        let mut input_match_end: i32 = -1;
-        let mut word_match_start: i32 = -1;
-        let mut strict_count = 0;
-        let mut match_count = 0;
-        while word_offset + word_index < word.len() && input_index < input.len() {
-            let c1 = word[word_index + word_offset];
-            let c2 = input[input_index];
-            if byte_char::match_char(c1, c2) {
-                if !matched {
-                    input_match_start = input_index as i32;
-                    word_match_start = word_index as i32 + word_offset as i32;
-                }
-                matched = true;
+        let mut word_index =
+            class.get_next_semantic_index(&word.chars, curr_match.word_match_end - 1);
+        while word_offset + word_index < next_match.word_match_start && input_index < input.len() {
+            if word.match_char(word_index + word_offset, input, input_index) {
                 input_index += 1;
-                strict_count += if c1 == c2 { 1 } else { 0 };
-                match_count += 1;
-            } else if matched {
-                input_index = last_input_index;
-                input_match_end = input_index as i32;
+                word_offset += 1;
+            } else {
+                word_index = class.get_next_semantic_index(&word.chars, word_index + word_offset);
+                word_offset = 0;
             }
-            word_offset = word_offset + 1;
-        }
-        if input_index >= input.len() {
-            matches.push(MatchRegion {
-                input_match_start: input_match_start as usize,
-                input_match_end: input_match_end as usize,
-                word_match_start: word_match_start as usize,
-                word_match_end: word_index + word_offset,
-                strict_ratio: strict_count as f64 / match_count as f64,
-                fuzzy: true,
-                index: 0,
-            });
-            return true;
         }
-        return false;
     }
-    return false;
+
     // remaining text fuzzy match
+    let last_input_index = input_index;
+    let mut matched = false;
+    let mut word_offset = 0;
+    let mut input_match_start = last_input_index;
+    let mut input_match_end = None;
+    let mut word_match_start = word_index;
+    let mut strict_count = 0;
+    let mut match_count = 0;
+    while word_offset + word_index < word.len() && input_index < input.len() {
+        if word.match_char(word_index + word_offset, input, input_index) {
+            if !matched {
+                input_match_start = input_index;
+                word_match_start = word_index + word_offset;
+            }
+            matched = true;
+            strict_count += if word.chars[word_index + word_offset] == input.chars[input_index] {
+                1
+            } else {
+                0
+            };
+            input_index += 1;
+            match_count += 1;
+        } else if matched {
+            if config.matching.disallow_fullfuzzy_matching {
+                break;
+            }
+            input_index = last_input_index;
+            input_match_end = Some(input_index);
+        }
+        word_offset += 1;
+    }
+    if input_index >= input.len() {
+        matches.push(MatchRegion {
+            input_match_start,
+            input_match_end: input_match_end.unwrap_or(input_index),
+            word_match_start,
+            word_match_end: word_index + word_offset,
+            strict_ratio: strict_count as f64 / match_count as f64,
+            fuzzy: true,
+            typo: false,
+            index: 0,
+        });
+        return true;
+    }
+    false
 }
 
 #[cfg(test)]
@@ -306,36 +1539,36 @@ mod test {
     #[test]
     fn basic() {
         {
-            let r = do_match("".as_bytes(), "a".as_bytes(), &[]);
+            let r = do_match("", "a", &[], &MatcherConfig::default());
             println!("'' match 'a' {:?}", r);
             assert!(r.0 >= 1.0);
         }
         {
-            let r = do_match("a".as_bytes(), "a".as_bytes(), &[]);
+            let r = do_match("a", "a", &[], &MatcherConfig::default());
             println!("'a' match 'a': {:?}", r);
             assert!((r.0 - 17.8).abs() < 0.001);
         }
 
         {
-            let r = do_match("ab".as_bytes(), "a".as_bytes(), &[]);
+            let r = do_match("ab", "a", &[], &MatcherConfig::default());
             println!("'ab' match 'a': {:?}", r);
             assert!(r.0 == 0.0);
         }
 
         {
-            let r = do_match("ab".as_bytes(), "ab".as_bytes(), &[]);
+            let r = do_match("ab", "ab", &[], &MatcherConfig::default());
             println!("'ab' match 'ab': {:?}", r);
             assert!((r.0 - 21.6).abs() < 0.0001);
         }
 
         {
-            let r = do_match("ab".as_bytes(), "a_b".as_bytes(), &[]);
+            let r = do_match("ab", "a_b", &[], &MatcherConfig::default());
             println!("'ab' match 'a_b': {:?}", r);
             assert!((r.0 - 21.2).abs() < 0.0001);
         }
 
         {
-            let r = do_match("ab".as_bytes(), "a_b_c".as_bytes(), &[]);
+            let r = do_match("ab", "a_b_c", &[], &MatcherConfig::default());
             println!("'ab' match 'a_b_c': {:?}", r);
             assert!((r.0 - 21.2).abs() < 0.0001);
         }
@@ -343,7 +1576,7 @@ mod test {
         {
             let lhs = "bora";
             let rhs = "border-raidus";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             assert_eq!(r.1.len(), 2);
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
@@ -368,7 +1601,7 @@ mod test {
         {
             let lhs = "woroff";
             let rhs = "word_offset";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             assert_eq!(r.1.len(), 2);
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
@@ -393,7 +1626,7 @@ mod test {
         {
             let lhs = "call";
             let rhs = "call";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
                 "call".as_bytes()
@@ -407,7 +1640,7 @@ mod test {
         {
             let lhs = "call";
             let rhs = "condition_all";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
                 "c".as_bytes()
@@ -430,7 +1663,7 @@ mod test {
         {
             let lhs = "Buffer";
             let rhs = "Buffer";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
@@ -445,7 +1678,7 @@ mod test {
         {
             let lhs = "Buffer";
             let rhs = "buffer";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
@@ -461,7 +1694,7 @@ mod test {
         {
             let lhs = "fmodify";
             let rhs = "fnamemodify";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             assert_eq!(r.1.len(), 2);
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
@@ -486,7 +1719,7 @@ mod test {
         {
             let lhs = "candlesingle";
             let rhs = "candle#accept#single";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
                 "candle".as_bytes()
@@ -510,7 +1743,7 @@ mod test {
         {
             let lhs = "conso";
             let rhs = "console";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert!((r.0 - 33.0).abs() < 0.0001);
         }
@@ -518,49 +1751,49 @@ mod test {
         {
             let lhs = "conso";
             let rhs = "ConstantSourceNode";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert!((r.0 - 30.0).abs() < 0.0001);
         }
         {
             let lhs = "var_";
             let rhs = "var_dump";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert!((r.0 - 29.2).abs() < 0.0001);
         }
         {
             let lhs = "my_";
             let rhs = "my_awesome_varible";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert!((r.0 - 25.4).abs() < 0.0001);
         }
         {
             let lhs = "my_";
             let rhs = "completion_matching_strategy";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert!((r.0 - 0.0).abs() < 0.0001);
         }
         {
             let lhs = "luacon";
             let rhs = "lua_context";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert!((r.0 - 35.6).abs() < 0.0001);
         }
         {
             let lhs = "call";
             let rhs = "calc";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert!((r.0 - 0.0).abs() < 0.0001);
         }
         {
             let lhs = "vi";
             let rhs = "void#";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert!((r.0 - 11.8).abs() < 0.0001);
         }
@@ -568,7 +1801,7 @@ mod test {
         {
             let lhs = "vo";
             let rhs = "void#";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert!((r.0 - 21.6).abs() < 0.0001);
         }
@@ -576,7 +1809,7 @@ mod test {
         {
             let lhs = "usela";
             let rhs = "useLayoutEffect";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert!((r.0 - 31.1).abs() < 0.0001);
         }
@@ -584,14 +1817,14 @@ mod test {
         {
             let lhs = "usela";
             let rhs = "useDataLayer";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("'{}' match '{}': {:?}", lhs, rhs, r);
             assert!((r.0 - 30.5).abs() < 0.0001);
         }
         {
             let lhs = "true";
             let rhs = "v:true";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &["true".as_bytes()]);
+            let r = do_match(lhs, rhs, &["true"], &MatcherConfig::default());
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
                 "true".as_bytes()
@@ -605,7 +1838,7 @@ mod test {
         {
             let lhs = "g";
             let rhs = "get";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &["get".as_bytes()]);
+            let r = do_match(lhs, rhs, &["get"], &MatcherConfig::default());
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
                 "g".as_bytes()
@@ -620,7 +1853,7 @@ mod test {
         {
             let lhs = "g";
             let rhs = "dein#get";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &["dein#get".as_bytes()]);
+            let r = do_match(lhs, rhs, &["dein#get"], &MatcherConfig::default());
             println!("{:?}", r);
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
@@ -636,7 +1869,7 @@ mod test {
         {
             let lhs = "2";
             let rhs = "[[2021";
-            let r = do_match(lhs.as_bytes(), rhs.as_bytes(), &[]);
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
             println!("{:?}", r);
             assert_eq!(
                 &lhs.as_bytes()[r.1[0].input_match_start..r.1[0].input_match_end],
@@ -650,4 +1883,676 @@ mod test {
         }
         // assert!(false);
     }
+
+    #[test]
+    fn config() {
+        assert!(MatcherConfig::default().validate().is_ok());
+        for boundary_order in [0, -1] {
+            let config = MatcherConfig {
+                boundary_order,
+                ..MatcherConfig::default()
+            };
+            assert!(config.validate().is_err());
+        }
+        let config = MatcherConfig {
+            boundary_order: 1,
+            ..MatcherConfig::default()
+        };
+        assert!(do_match("ab", "a_b", &[], &config).0 > 0.0);
+    }
+
+    #[test]
+    fn matching_option() {
+        let config = |f: fn(&mut MatchingOption)| {
+            let mut config = MatcherConfig::default();
+            f(&mut config.matching);
+            config
+        };
+        {
+            let r = do_match("fmodify", "fnamemodify", &[], &MatcherConfig::default());
+            assert!(r.0 > 1.0);
+            let c = config(|o| o.disallow_fuzzy_matching = true);
+            let r = do_match("fmodify", "fnamemodify", &[], &c);
+            assert!(r.0 == 0.0);
+        }
+        {
+            let c = config(|o| o.disallow_fullfuzzy_matching = true);
+            let r = do_match("fmodify", "fnamemodify", &[], &c);
+            assert!(r.0 == 0.0);
+            let r = do_match("fnamemo", "fnamemodify", &[], &c);
+            assert!(r.0 > 1.0);
+        }
+        {
+            let r = do_match(
+                "ally",
+                "condition_all_ready",
+                &[],
+                &MatcherConfig::default(),
+            );
+            assert!(r.0 == 0.0);
+            let c = config(|o| o.disallow_partial_fuzzy_matching = false);
+            let r = do_match("ally", "condition_all_ready", &[], &c);
+            assert!(r.0 > 1.0);
+            let r = do_match("ion", "condition_all_ready", &[], &c);
+            assert!((r.0 - 1.0).abs() < 0.0001);
+            assert!(r.1[0].fuzzy);
+        }
+        {
+            let c = config(|o| o.disallow_partial_matching = true);
+            let r = do_match("bora", "border-radius", &[], &c);
+            assert!(r.0 == 0.0);
+            let r = do_match("bord", "border-radius", &[], &c);
+            assert!(r.0 > 1.0);
+        }
+        {
+            let c = config(|o| o.disallow_prefix_unmatching = true);
+            let r = do_match("call", "condition_all", &[], &c);
+            assert!(r.0 > 1.0);
+            let r = do_match("all", "condition_all", &[], &c);
+            assert!(r.0 == 0.0);
+            let r = do_match("all", "condition_all", &[], &MatcherConfig::default());
+            assert!(r.0 > 1.0);
+        }
+        {
+            let r = do_match("_all", "condition_all", &[], &MatcherConfig::default());
+            assert!(r.0 == 0.0);
+            let c = config(|o| o.disallow_symbol_nonprefix_matching = false);
+            let r = do_match("_all", "condition_all", &[], &c);
+            assert!(r.0 > 1.0);
+        }
+    }
+
+    #[test]
+    fn matching_override() {
+        let mut config = MatcherConfig::default();
+        config.matching.ignore_diacritics = true;
+        config.matching.max_typos = 1;
+        // the `matching` table of cmp
+        let matching: MatchingOverride = serde_json::from_value(serde_json::json!({
+            "disallow_fuzzy_matching": true,
+            "disallow_partial_fuzzy_matching": true,
+            "disallow_prefix_unmatching": false,
+        }))
+        .unwrap();
+        matching.apply(&mut config.matching);
+        assert!(config.matching.disallow_fuzzy_matching);
+        assert!(config.matching.ignore_diacritics);
+        assert_eq!(config.matching.max_typos, 1);
+        assert!(!config.matching.multi_term);
+
+        MatchingOverride {
+            ignore_diacritics: Some(false),
+            ..MatchingOverride::default()
+        }
+        .apply(&mut config.matching);
+        assert!(!config.matching.ignore_diacritics);
+        assert!(config.matching.disallow_fuzzy_matching);
+    }
+
+    #[test]
+    fn many() {
+        let candidates = [
+            Candidate {
+                word: "condition_all".to_string(),
+                words: vec![],
+            },
+            Candidate {
+                word: "calc".to_string(),
+                words: vec![],
+            },
+            Candidate {
+                word: "call".to_string(),
+                words: vec![],
+            },
+            Candidate {
+                word: "v:call".to_string(),
+                words: vec!["call".to_string()],
+            },
+        ];
+        let r = match_many("call", &candidates, &MatcherConfig::default());
+        let indices: Vec<usize> = r.iter().map(|m| m.index).collect();
+        assert_eq!(indices, vec![2, 3, 0]);
+        for m in r {
+            let candidate = &candidates[m.index];
+            let words: Vec<&str> = candidate.words.iter().map(|w| w.as_str()).collect();
+            let (score, _) = do_match("call", &candidate.word, &words, &MatcherConfig::default());
+            assert!((m.score - score).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn query() {
+        let config = MatcherConfig::default();
+        let query = Query::new("usela", &config);
+        for word in ["useLayoutEffect", "useDataLayer", "useless", "user"] {
+            let r = query.do_match(word, &[]);
+            let expected = do_match("usela", word, &[], &config);
+            assert!((r.0 - expected.0).abs() < 0.0001);
+            assert_eq!(r.1.len(), expected.1.len());
+        }
+        assert!(query.do_match("user", &[]).0 == 0.0);
+
+        let query = Query::new("fooBar_baz", &config);
+        assert_eq!(query.boundaries(), vec![0, 3, 6, 7]);
+    }
+
+    #[test]
+    fn char_bag() {
+        let bag = CharBag::new("Border-Radius");
+        assert_eq!(bag.missing(&CharBag::new("bora")), 0);
+        assert_eq!(bag.missing(&CharBag::new("BR-")), 0);
+        assert_eq!(bag.missing(&CharBag::new("borax")), 1);
+        assert_eq!(bag.missing(&CharBag::new("bora2")), 1);
+        assert_eq!(bag.missing(&CharBag::new("borax2")), 2);
+        assert_eq!(CharBag::new("Ärger").missing(&CharBag::new("ä")), 0);
+
+        // the prefilter never rejects a word that would match
+        let config = MatcherConfig::default();
+        for (input, word) in [
+            ("woroff", "word_offset"),
+            ("candlesingle", "candle#accept#single"),
+            ("true", "v:true"),
+            ("fmodify", "fnamemodify"),
+            ("usela", "useDataLayer"),
+        ] {
+            let query = Query::new(input, &config);
+            let r = query.do_match(word, &[]);
+            assert!(r.0 >= 1.0);
+            assert!((r.0 - query.do_match_without_prefilter(word, &[]).0).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn acronym() {
+        let config = MatcherConfig::default();
+        let words = |word: &str, r: &(f64, Vec<MatchRegion>)| -> Vec<String> {
+            r.1.iter()
+                .map(|m| word[m.word_match_start..m.word_match_end].to_string())
+                .collect()
+        };
+        {
+            let r = do_match("gsp", "getStaticProps", &[], &config);
+            println!("'gsp' match 'getStaticProps': {:?}", r);
+            assert_eq!(words("getStaticProps", &r), vec!["g", "S", "P"]);
+            assert!(r.0 > config.prefix + config.acronym);
+            let s = do_match("gsp", "get_static_props", &[], &config);
+            assert_eq!(words("get_static_props", &s), vec!["g", "s", "p"]);
+            assert!(s.0 > config.prefix + config.acronym);
+            // strict case wins
+            assert!(r.0 < s.0);
+            let r = do_match("gSP", "getStaticProps", &[], &config);
+            let s = do_match("gSP", "get_static_props", &[], &config);
+            assert!(r.0 > s.0);
+        }
+        {
+            // skips a boundary
+            let r = do_match("gsp", "getServerSideProps", &[], &config);
+            assert_eq!(words("getServerSideProps", &r), vec!["g", "S", "P"]);
+            assert!(r.0 < do_match("gsp", "getStaticProps", &[], &config).0);
+        }
+        {
+            let r = do_match("HTMLIE", "HTMLInputElement", &[], &config);
+            assert_eq!(words("HTMLInputElement", &r), vec!["HTMLI", "E"]);
+            assert!(r.0 > config.prefix + config.acronym);
+            // skips a part of the uppercase run
+            let r = do_match("HIE", "HTMLInputElement", &[], &config);
+            assert_eq!(words("HTMLInputElement", &r), vec!["H", "I", "E"]);
+            let e = explain("HIE", "HTMLInputElement", &[], &config);
+            assert_eq!(e.acronym_bonus, config.acronym);
+            assert_eq!(e.regions.len(), 3);
+            assert_eq!(e.score, r.0);
+        }
+        {
+            // ranks above fuzzier candidates
+            let inputs = ["gasp", "gossipProtocol", "getStaticProps", "grasp"];
+            let mut scores: Vec<(f64, &str)> = inputs
+                .iter()
+                .map(|w| (do_match("gsp", w, &[], &config).0, *w))
+                .collect();
+            scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+            assert_eq!(scores[0].1, "getStaticProps");
+        }
+        {
+            // a regular match is still preferred when it is better
+            let r = do_match("get", "getStaticProps", &[], &config);
+            assert_eq!(words("getStaticProps", &r), vec!["get"]);
+            let mut c = MatcherConfig::default();
+            c.matching.disallow_partial_matching = true;
+            assert!(do_match("HTMLIE", "HTMLInputElement", &[], &c).0 == 0.0);
+        }
+        {
+            // the pass needs 3 chars, so the input can match when a shorter one didn't
+            assert!(do_match("tml", "HTMLInputElement", &[], &config).0 == 0.0);
+            assert!(do_match("tmle", "HTMLInputElement", &[], &config).0 > 0.0);
+            let mut c = MatcherConfig::default();
+            c.matching.acronym_matching = false;
+            assert!(do_match("tmle", "HTMLInputElement", &[], &c).0 == 0.0);
+            assert_eq!(explain("gsp", "getStaticProps", &[], &c).acronym_bonus, 0.0);
+        }
+    }
+
+    #[test]
+    fn longer_input() {
+        // the entries that `SourceCache` keeps for the next input, typed one char at a time,
+        // must be a superset of the ones that match it
+        let words = [
+            "HTMLInputElement",
+            "html",
+            "template",
+            "MyInputEvent",
+            "timeline",
+            "tMlE",
+            "src/html/main.rs",
+        ];
+        let typed: [&[&str]; 5] = [
+            &["t", "tm", "tml", "tmle"],
+            &["M", "MI", "MIE"],
+            &["M", "Ml", "Mli", "Mlie"],
+            &["t", "tM", "tMl", "tMlE"],
+            &["h", "ht", "htm", "html", "html ", "html m"],
+        ];
+        let mut configs = vec![MatcherConfig::default(), MatcherConfig::default()];
+        configs[1].matching.multi_term = true;
+        configs[1].strategy = Strategy::Path;
+        for config in &configs {
+            for inputs in typed {
+                let mut kept = words.to_vec();
+                for input in inputs {
+                    let query = Query::new(input, config);
+                    let matches = |w: &&str| query.do_match(w, &[]).0 > 0.0;
+                    let refiltered: Vec<&str> = words.iter().copied().filter(matches).collect();
+                    let cached: Vec<&str> = kept.iter().copied().filter(matches).collect();
+                    assert_eq!(cached, refiltered, "input {:?}", input);
+                    kept.retain(|w| matches(w) || query.may_match_longer(w));
+                }
+            }
+        }
+        let query = Query::new("tml", &MatcherConfig::default());
+        assert!(query.may_match_longer("HTMLInputElement"));
+        assert!(!query.may_match_longer("template"));
+    }
+
+    #[test]
+    fn multi_term() {
+        assert_eq!(
+            split_terms("  foo bar\tbaz "),
+            vec![(2, "foo"), (6, "bar"), (10, "baz")]
+        );
+
+        let mut config = MatcherConfig::default();
+        assert!(do_match("foo bar", "bar_foo", &[], &config).0 == 0.0);
+
+        config.matching.multi_term = true;
+        {
+            let input = "foo bar";
+            let word = "bar_foo";
+            let r = do_match(input, word, &[], &config);
+            println!("'{}' match '{}': {:?}", input, word, r);
+            let foo = do_match("foo", word, &[], &config);
+            let bar = do_match("bar", word, &[], &config);
+            assert!((r.0 - (foo.0 + bar.0)).abs() < 0.0001);
+            // sorted by their position in the word
+            assert_eq!(r.1.len(), 2);
+            assert_eq!(&word[r.1[0].word_match_start..r.1[0].word_match_end], "bar");
+            assert_eq!(
+                &input[r.1[0].input_match_start..r.1[0].input_match_end],
+                "bar"
+            );
+            assert_eq!(&word[r.1[1].word_match_start..r.1[1].word_match_end], "foo");
+            assert_eq!(
+                &input[r.1[1].input_match_start..r.1[1].input_match_end],
+                "foo"
+            );
+        }
+        {
+            // every term has to match
+            assert!(do_match("foo baz", "bar_foo", &[], &config).0 == 0.0);
+            assert!(do_match("local func", "local function foo()", &[], &config).0 > 0.0);
+            // overlapping regions are merged
+            let r = do_match("local loc", "local function foo()", &[], &config);
+            assert_eq!(r.1.len(), 1);
+            // a single term matches as usual
+            assert_eq!(
+                do_match(" foo ", "bar_foo", &[], &config).0,
+                do_match("foo", "bar_foo", &[], &config).0
+            );
+            assert_eq!(
+                do_match("  ", "bar_foo", &[], &config).0,
+                do_match("", "bar_foo", &[], &config).0
+            );
+        }
+    }
+
+    #[test]
+    fn algorithm() {
+        let config = MatcherConfig {
+            algorithm: Algorithm::Fzy,
+            ..MatcherConfig::default()
+        };
+        let word = "äxxb_äb";
+        let r = do_match("ÄB", word, &[], &config);
+        assert!(r.0 > 1.0);
+        assert_eq!(r.1.len(), 1);
+        assert_eq!(&word[r.1[0].word_match_start..r.1[0].word_match_end], "äb");
+        assert_eq!(r.1[0].input_match_end, "ÄB".len());
+        assert!(do_match("ba", "ab", &[], &config).0 == 0.0);
+    }
+
+    #[test]
+    fn char_class() {
+        let none = MatcherConfig::default();
+        let vim = MatcherConfig {
+            char_class: CharClass::new("#:"),
+            ..MatcherConfig::default()
+        };
+        let css = MatcherConfig {
+            char_class: CharClass::new("-"),
+            ..MatcherConfig::default()
+        };
+        // symbols only match in a prefix match, unless they are part of identifiers
+        assert!(do_match("dein#g", "call dein#get", &[], &none).0 == 0.0);
+        assert!(do_match("dein#g", "call dein#get", &[], &vim).0 > 0.0);
+        assert!(do_match("g:v", "let g:var", &[], &none).0 == 0.0);
+        assert!(do_match("g:v", "let g:var", &[], &vim).0 > 0.0);
+        // the letters after the keyword chars are still word boundaries
+        assert!(do_match("bora", "border-radius", &[], &css).0 > 0.0);
+        assert_eq!(
+            do_match("dg", "dein#get", &[], &vim).1[1].index,
+            do_match("dg", "dein_get", &[], &none).1[1].index - 1
+        );
+    }
+
+    #[test]
+    fn diacritics() {
+        let mut config = MatcherConfig::default();
+        assert!(do_match("cafe", "café", &[], &config).0 == 0.0);
+
+        config.matching.ignore_diacritics = true;
+        {
+            let lhs = "cafe";
+            let rhs = "café";
+            let r = do_match(lhs, rhs, &[], &config);
+            println!("'{}' match '{}': {:?}", lhs, rhs, r);
+            assert!(r.0 > 1.0);
+            // regions are in the original word
+            assert_eq!(&rhs[r.1[0].word_match_start..r.1[0].word_match_end], "café");
+            assert!((r.1[0].strict_ratio - 0.75).abs() < 0.0001);
+            // strict matches win
+            assert!(do_match("café", rhs, &[], &config).0 > r.0);
+            assert_eq!(
+                do_match("café", rhs, &[], &config).0,
+                do_match("café", rhs, &[], &MatcherConfig::default()).0
+            );
+        }
+        {
+            assert!(do_match("naive", "naïve", &[], &config).0 > 1.0);
+            assert!(do_match("zurich", "Zürich", &[], &config).0 > 1.0);
+            assert!(do_match("Zur", "ZÜRICH", &[], &config).0 > 1.0);
+            // both sides are folded
+            assert!(do_match("naïve", "naive", &[], &config).0 > 1.0);
+            assert!(do_match("zü", "zurich", &[], &config).0 > 1.0);
+            assert!(do_match("ß", "ss", &[], &config).0 == 0.0);
+        }
+    }
+
+    #[test]
+    fn smart_case() {
+        let mut config = MatcherConfig::default();
+        config.matching.smart_case = true;
+        {
+            let lhs = "Buffer";
+            let rhs = "Buffer";
+            let r = do_match(lhs, rhs, &[], &config);
+            println!("'{}' match '{}': {:?}", lhs, rhs, r);
+            assert_eq!(
+                &rhs.as_bytes()[r.1[0].word_match_start..r.1[0].word_match_end],
+                "Buffer".as_bytes()
+            );
+            assert!((r.0 - 36.8).abs() < 0.0001);
+        }
+        {
+            // the uppercase `B` can't match `b`
+            let r = do_match("Buffer", "buffer", &[], &config);
+            assert!(r.0 == 0.0);
+        }
+        {
+            // an input without uppercase letters still ignores case
+            let lhs = "buffer";
+            let rhs = "Buffer";
+            let r = do_match(lhs, rhs, &[], &config);
+            assert_eq!(r.0, do_match(lhs, rhs, &[], &MatcherConfig::default()).0);
+            assert!(r.0 > 1.0);
+        }
+        {
+            assert!(do_match("bufL", "bufferLine", &[], &config).0 > 1.0);
+            assert!(do_match("bufL", "buffer_line", &[], &config).0 == 0.0);
+            assert!(do_match("bufL", "buffer_line", &[], &MatcherConfig::default()).0 > 1.0);
+            // fuzzy pass
+            assert!(do_match("bufLn", "buffer_Line", &[], &config).0 > 0.0);
+            assert!(do_match("bufLn", "buffer_line", &[], &config).0 == 0.0);
+            // prefix bonus from the words
+            let r = do_match("True", "v:True", &["True"], &config);
+            let s = do_match("True", "v:True", &["true"], &config);
+            assert!(r.0 > s.0);
+        }
+    }
+
+    #[test]
+    fn typo() {
+        let mut config = MatcherConfig::default();
+        assert!(do_match("retrun", "return", &[], &config).0 == 0.0);
+
+        config.matching.max_typos = 2;
+        {
+            let lhs = "retrun";
+            let rhs = "return";
+            let r = do_match(lhs, rhs, &[], &config);
+            println!("'{}' match '{}': {:?}", lhs, rhs, r);
+            assert!(r.0 > 0.0 && r.0 < 1.0);
+            assert!(r.1[0].typo);
+            assert_eq!(
+                &rhs[r.1[0].word_match_start..r.1[0].word_match_end],
+                "return"
+            );
+        }
+        {
+            let lhs = "lenght";
+            let rhs = "length_of";
+            let r = do_match(lhs, rhs, &[], &config);
+            assert!(r.0 > 0.0 && r.0 < 1.0);
+            assert_eq!(
+                &rhs[r.1[0].word_match_start..r.1[0].word_match_end],
+                "length"
+            );
+        }
+        {
+            let lhs = "compnent";
+            let rhs = "ReactComponent";
+            let r = do_match(lhs, rhs, &[], &config);
+            assert!(r.0 > 0.0);
+            assert_eq!(
+                &rhs[r.1[0].word_match_start..r.1[0].word_match_end],
+                "Component"
+            );
+            // typos at the beginning of the word rank higher
+            assert!(do_match(lhs, "Component", &[], &config).0 > r.0);
+        }
+        {
+            // the typo-tolerant pass only runs when nothing else matches
+            let r = do_match("fmodify", "fnamemodify", &[], &config);
+            assert!(r.0 > 1.0);
+            assert!(!r.1.iter().any(|m| m.typo));
+            // too short to allow typos
+            assert!(do_match("ab", "ba", &[], &config).0 == 0.0);
+            assert!(do_match("foo", "bar", &[], &config).0 == 0.0);
+        }
+        {
+            // words missing more chars than typos are rejected early, the others aren't
+            for (input, word) in [("retrun", "return"), ("funcsion", "function")] {
+                let query = Query::new(input, &config);
+                let r = query.do_match(word, &[]);
+                assert!(r.0 > 0.0);
+                assert_eq!(r.0, query.do_match_without_prefilter(word, &[]).0);
+            }
+            let e = explain("fxyzion", "function", &[], &config);
+            assert_eq!(e.rejected, Some("missing chars"));
+        }
+    }
+
+    #[test]
+    fn explanation() {
+        let config = MatcherConfig::default();
+        let cases: [(&str, &str, &[&str]); 12] = [
+            ("", "a", &[]),
+            ("ab", "a_b_c", &[]),
+            ("bora", "border-radius", &[]),
+            ("woroff", "word_offset", &[]),
+            ("call", "condition_all", &[]),
+            ("buffer", "Buffer", &[]),
+            ("fmodify", "fnamemodify", &[]),
+            ("candlesingle", "candle#accept#single", &[]),
+            ("true", "v:true", &["true"]),
+            ("ion", "function", &[]),
+            ("qzx", "function", &[]),
+            ("vi", "void#", &[]),
+        ];
+        for (input, word, words) in cases {
+            let matched = do_match(input, word, words, &config);
+            let e = explain(input, word, words, &config);
+            println!("'{}' explain '{}': {:?}", input, word, e);
+            assert_eq!(e.score, matched.0);
+            assert_eq!(e.rejected.is_some(), matched.0 == 0.0);
+            if e.rejected.is_none() && !e.regions.is_empty() {
+                let sum = e.prefix_bonus
+                    + e.regions.iter().map(|r| r.score).sum::<f64>()
+                    + e.not_fuzzy_bonus
+                    + e.acronym_bonus;
+                assert!((sum - e.score).abs() < 1e-9);
+            }
+        }
+
+        let e = explain("woroff", "word_offset", &[], &config);
+        assert!(e.prefix);
+        assert_eq!(e.prefix_bonus, config.prefix);
+        assert_eq!(e.regions.len(), 2);
+        assert_eq!(e.regions[0].base, 3.0);
+        assert_eq!(e.regions[1].region.word_match_start, 5);
+        assert_eq!(e.regions[1].boundary_multiplier, 1.7);
+        assert!(e.fuzzy_tail.is_none());
+        assert_eq!(e.not_fuzzy_bonus, config.not_fuzzy);
+
+        let e = explain("fmodify", "fnamemodify", &[], &config);
+        assert_eq!(e.not_fuzzy_bonus, 0.0);
+        assert!(e.fuzzy_tail.unwrap().fuzzy);
+
+        let e = explain("Buffer", "buffer", &[], &config);
+        assert_eq!(e.regions[0].strict_multiplier, 1.0 + 5.0 / 6.0);
+
+        assert_eq!(
+            explain("qzx", "function", &[], &config).rejected,
+            Some("missing chars")
+        );
+
+        // nothing of the failed cmp pass is left when the typo pass matches
+        let mut typo = MatcherConfig::default();
+        typo.matching.max_typos = 1;
+        let matched = do_match("retrun", "return", &[], &typo);
+        let e = explain("retrun", "return", &[], &typo);
+        assert!(e.typo && !e.prefix && e.rejected.is_none());
+        assert_eq!(e.prefix_bonus, 0.0);
+        assert_eq!(e.not_fuzzy_bonus, 0.0);
+        assert_eq!(e.score, matched.0);
+        assert_eq!(e.regions.len(), 1);
+        assert_eq!(e.regions[0].score, 0.0);
+        assert_eq!(e.regions[0].region.word_match_end, 6);
+
+        let fzy = MatcherConfig {
+            algorithm: Algorithm::Fzy,
+            ..MatcherConfig::default()
+        };
+        let matched = do_match("gsp", "getStaticProps", &[], &fzy);
+        let e = explain("gsp", "getStaticProps", &[], &fzy);
+        assert_eq!(e.score, matched.0);
+        assert_eq!(e.regions.len(), matched.1.len());
+
+        let mut terms = MatcherConfig::default();
+        terms.matching.multi_term = true;
+        let matched = do_match("line buf", "BufferLine", &[], &terms);
+        let e = explain("line buf", "BufferLine", &[], &terms);
+        assert_eq!(e.score, matched.0);
+        assert_eq!(e.regions.len(), 2);
+        assert_eq!(e.regions[0].region.input_match_start, 5);
+    }
+
+    #[test]
+    fn positions() {
+        let config = MatcherConfig::default();
+        let input = "にほん";
+        let word = "🙂_にほんご";
+        let r = do_match(input, word, &[], &config);
+        assert_eq!(r.1.len(), 1);
+        let p = r.1[0].positions(input, word);
+        assert_eq!(p.input_col, (1, 6));
+        assert_eq!(p.input_utf16, (1, 3));
+        assert_eq!(p.word_col, (4, 9));
+        assert_eq!(p.word_utf16, (4, 6));
+
+        let input = "ab";
+        let word = "a_b";
+        let r = do_match(input, word, &[], &config);
+        let p = r.1[1].positions(input, word);
+        assert_eq!(p.word_col, (3, 3));
+        assert_eq!(p.word_utf16, (3, 3));
+    }
+
+    #[test]
+    fn unicode() {
+        {
+            let lhs = "ärg";
+            let rhs = "Ärger";
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
+            println!("'{}' match '{}': {:?}", lhs, rhs, r);
+            assert_eq!(
+                &lhs[r.1[0].input_match_start..r.1[0].input_match_end],
+                "ärg"
+            );
+            assert_eq!(&rhs[r.1[0].word_match_start..r.1[0].word_match_end], "Ärg");
+            assert!((r.0 - 23.5).abs() < 0.0001);
+        }
+        {
+            let lhs = "привет";
+            let rhs = "Привет";
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
+            println!("'{}' match '{}': {:?}", lhs, rhs, r);
+            assert_eq!(
+                &rhs[r.1[0].word_match_start..r.1[0].word_match_end],
+                "Привет"
+            );
+            assert!((r.0 - 34.9).abs() < 0.0001);
+        }
+        {
+            let lhs = "λογος";
+            let rhs = "ΛΟΓΟΣ";
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
+            println!("'{}' match '{}': {:?}", lhs, rhs, r);
+            assert_eq!(
+                &rhs[r.1[0].word_match_start..r.1[0].word_match_end],
+                "ΛΟΓΟΣ"
+            );
+            assert!(r.0 > 1.0);
+        }
+        {
+            let lhs = "übst";
+            let rhs = "über_straße";
+            let r = do_match(lhs, rhs, &[], &MatcherConfig::default());
+            println!("'{}' match '{}': {:?}", lhs, rhs, r);
+            assert_eq!(r.1.len(), 2);
+            assert_eq!(&lhs[r.1[1].input_match_start..r.1[1].input_match_end], "st");
+            assert_eq!(&rhs[r.1[1].word_match_start..r.1[1].word_match_end], "st");
+        }
+        {
+            let r = do_match("ä", "a", &[], &MatcherConfig::default());
+            assert!(r.0 == 0.0);
+        }
+    }
 }
@@ -1,6 +1,9 @@
 use mlua::prelude::*;
 use serde::ser::{Serialize, Serializer};
 use serde_json;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use unicode_width::UnicodeWidthChar;
 pub fn to_vimindex(text: &str, utfindex: usize) -> usize {
     let mut r = 0;
     let mut chars = text.chars();
@@ -25,6 +28,72 @@ pub fn to_utfindex(text: &str, mut vim_index: usize) -> usize {
     utfindex
 }
 
+/// Display width in cells of the text before the byte offset, East Asian wide chars and emoji
+/// take 2 cells.
+pub fn to_display_col(text: &str, byte_index: usize) -> usize {
+    text.char_indices()
+        .take_while(|(i, _)| *i < byte_index)
+        .map(|(_, c)| c.width().unwrap_or(0))
+        .sum()
+}
+
+/// Number of UTF-16 code units of the text before the byte offset.
+pub fn to_utf16_index(text: &str, byte_index: usize) -> usize {
+    text.char_indices()
+        .take_while(|(i, _)| *i < byte_index)
+        .map(|(_, c)| c.len_utf16())
+        .sum()
+}
+
+/// A score and a position, ordered from the worst to the best: the lower score, then the later
+/// position.
+#[derive(Debug, Clone, Copy)]
+struct Rank(f64, usize);
+
+impl PartialEq for Rank {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Rank {}
+
+impl PartialOrd for Rank {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rank {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .total_cmp(&other.0)
+            .then_with(|| other.1.cmp(&self.1))
+    }
+}
+
+/// The `k` items with the highest scores, from the highest to the lowest. Items with the same
+/// score keep their order, i.e. this is a stable sort by score followed by a truncation.
+pub fn top_k<T>(items: Vec<T>, k: usize, score: impl Fn(&T) -> f64) -> Vec<T> {
+    // a min-heap of the best ranks so far
+    let mut heap = BinaryHeap::<Reverse<Rank>>::with_capacity(std::cmp::min(k, items.len()));
+    for (i, item) in items.iter().enumerate() {
+        let rank = Rank(score(item), i);
+        if heap.len() < k {
+            heap.push(Reverse(rank));
+        } else if let Some(mut worst) = heap.peek_mut() {
+            if rank > worst.0 {
+                *worst = Reverse(rank);
+            }
+        }
+    }
+    let mut selected: Vec<Option<T>> = items.into_iter().map(Some).collect();
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(rank)| selected[rank.1].take().unwrap())
+        .collect()
+}
+
 pub fn inspect(lua: &Lua, lua_value: &LuaValue) -> LuaResult<String> {
     let mut writer = Vec::with_capacity(128);
     let mut serializer = serde_json::Serializer::new(&mut writer);
@@ -51,3 +120,26 @@ pub fn inspect(lua: &Lua, lua_value: &LuaValue) -> LuaResult<String> {
 
     Ok(String::from_utf8(writer).unwrap())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn top_k_cases() {
+        let scores = [3.0, 1.0, 3.0, 7.5, 0.5, 7.5, 3.0, 2.0, 7.5, 1.0];
+        let items: Vec<(usize, f64)> = scores.iter().copied().enumerate().collect();
+        for k in 0..=items.len() + 1 {
+            let mut expected = items.clone();
+            expected.sort_by(|a, b| b.1.total_cmp(&a.1));
+            expected.truncate(k);
+            assert_eq!(top_k(items.clone(), k, |i| i.1), expected);
+        }
+        let top = top_k(items, 4, |i| i.1);
+        assert_eq!(
+            top.iter().map(|i| i.0).collect::<Vec<usize>>(),
+            vec![3, 5, 8, 0]
+        );
+        assert_eq!(top_k(vec![1.0, 2.0], usize::MAX, |i| *i), vec![2.0, 1.0]);
+    }
+}
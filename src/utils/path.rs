@@ -0,0 +1,158 @@
+//! The `path` matching strategy, for file and module names.
+//!
+//! The input and the word are split on `/` and every segment of the input has to match a
+//! segment of the word, in order. Directories of the word can be skipped (`src/ma` matches
+//! `src/utils/matcher.rs`), and the last segment of the input prefers the basename of the word.
+
+use crate::utils::matcher::{MatchRegion, MatcherConfig, Query};
+
+/// Splits a path into its non-empty segments with their byte offsets, skipping the `.`
+/// segments (`./src/` is `src`).
+pub fn split(path: &str) -> Vec<(usize, &str)> {
+    let mut segments = Vec::new();
+    let mut offset = 0;
+    for segment in path.split('/') {
+        if !segment.is_empty() && segment != "." {
+            segments.push((offset, segment));
+        }
+        offset += segment.len() + 1;
+    }
+    segments
+}
+
+/// The queries of the input segments.
+///
+/// A `.` in a segment matches anywhere, so that `.rs` finds the extension of `matcher.rs`.
+pub fn compile(input: &str, config: &MatcherConfig) -> Vec<(usize, Query)> {
+    let mut config = config.clone();
+    config.strategy = Default::default();
+    config.matching.disallow_symbol_nonprefix_matching = false;
+    split(input)
+        .into_iter()
+        .map(|(offset, segment)| (offset, Query::new(segment, &config)))
+        .collect()
+}
+
+/// Matches the compiled input segments against the word.
+///
+/// The score is the sum of the segment scores, plus the `basename` bonus of the config when the
+/// last input segment matches the basename of the word.
+pub fn match_segments(
+    segments: &[(usize, Query)],
+    word: &str,
+    config: &MatcherConfig,
+) -> (f64, Vec<MatchRegion>) {
+    let word_segments = split(word);
+    let mut score = 0.0;
+    let mut matches = Vec::new();
+    let mut word_index = 0;
+    for (i, (input_offset, query)) in segments.iter().enumerate() {
+        let mut found = None;
+        if i + 1 == segments.len() && word_index < word_segments.len() {
+            let basename = word_segments.len() - 1;
+            let matched = query.do_match(word_segments[basename].1, &[]);
+            if matched.0 > 0.0 {
+                found = Some((basename, matched.0 + config.basename, matched.1));
+            }
+        }
+        if found.is_none() {
+            found = (word_index..word_segments.len()).find_map(|j| {
+                let matched = query.do_match(word_segments[j].1, &[]);
+                if matched.0 > 0.0 {
+                    Some((j, matched.0, matched.1))
+                } else {
+                    None
+                }
+            });
+        }
+        let (j, segment_score, segment_matches) = match found {
+            Some(found) => found,
+            None => return (0.0, Vec::new()),
+        };
+        let word_offset = word_segments[j].0;
+        score += segment_score;
+        matches.extend(segment_matches.into_iter().map(|mut m| {
+            m.input_match_start += input_offset;
+            m.input_match_end += input_offset;
+            m.word_match_start += word_offset;
+            m.word_match_end += word_offset;
+            m.index = j;
+            m
+        }));
+        word_index = j + 1;
+    }
+    (score, matches)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::matcher::Strategy;
+
+    fn path_config() -> MatcherConfig {
+        MatcherConfig {
+            strategy: Strategy::Path,
+            ..MatcherConfig::default()
+        }
+    }
+
+    fn regions(word: &str, matched: &(f64, Vec<MatchRegion>)) -> Vec<String> {
+        matched
+            .1
+            .iter()
+            .map(|m| word[m.word_match_start..m.word_match_end].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn splitting() {
+        assert_eq!(
+            split("./src//utils/ma"),
+            vec![(2, "src"), (7, "utils"), (13, "ma")]
+        );
+        assert_eq!(split("../lib.rs"), vec![(0, ".."), (3, "lib.rs")]);
+        assert!(split("./").is_empty());
+    }
+
+    #[test]
+    fn segments() {
+        let config = path_config();
+        let m = |input: &str, word: &str| Query::new(input, &config).do_match(word, &[]);
+        {
+            let input = "./src/utils/ma";
+            let word = "src/utils/matcher.rs";
+            let r = m(input, word);
+            println!("'{}' match '{}': {:?}", input, word, r);
+            assert!(r.0 > config.basename);
+            assert_eq!(regions(word, &r), vec!["src", "utils", "ma"]);
+            assert_eq!(
+                &input[r.1[2].input_match_start..r.1[2].input_match_end],
+                "ma"
+            );
+        }
+        {
+            // directories can be skipped, not reordered
+            assert!(m("src/ma", "src/utils/matcher.rs").0 > 0.0);
+            assert!(m("utils/src", "src/utils/matcher.rs").0 == 0.0);
+            assert!(m("src/", "src/lib.rs").0 > 0.0);
+            assert!(m("src/", "lib/source.rs").0 == 0.0);
+            assert!(m("./", "lib/source.rs").0 > 0.0);
+        }
+        {
+            // matches in the basename rank higher
+            let r = m("ma", "src/matcher.rs");
+            let s = m("ma", "main/lib.rs");
+            assert_eq!(regions("main/lib.rs", &s), vec!["ma"]);
+            assert!(r.0 > s.0);
+            assert!(r.0 > m("ma", "src/my_matcher.rs").0);
+        }
+        {
+            // extensions and dotfiles
+            let word = "src/matcher.rs";
+            let r = m(".rs", word);
+            assert_eq!(regions(word, &r), vec![".rs"]);
+            assert!(m(".gi", ".gitignore").0 > 0.0);
+            assert!(m(".rs", "src/rust/main.c").0 == 0.0);
+        }
+    }
+}
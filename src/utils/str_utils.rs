@@ -1,6 +1,7 @@
 use std::collections::HashSet;
 
 use super::byte_char;
+use super::char_class::CharClass;
 
 pub fn is_invalid_chars(c: u8) -> bool {
     match c {
@@ -32,7 +33,12 @@ pub fn pair_chars(c: u8) -> Option<u8> {
     }
 }
 
-pub fn get_word_with_min_len(text: &str, stop_char: u8, min_length: usize) -> String {
+pub fn get_word_with_min_len(
+    text: &str,
+    stop_char: u8,
+    min_length: usize,
+    class: &CharClass,
+) -> String {
     let mut has_alnum = false;
     let mut word = Vec::new();
     let mut stack = Vec::new();
@@ -54,7 +60,7 @@ pub fn get_word_with_min_len(text: &str, stop_char: u8, min_length: usize) -> St
     for c in text.bytes() {
         if word.len() < min_length {
             word.push(c);
-        } else if !is_invalid_chars(c) {
+        } else if !class.is_invalid_char(c) {
             add(&mut word, &mut stack, c);
             has_alnum = has_alnum || byte_char::is_alnum(c);
         } else if !has_alnum {
@@ -79,8 +85,8 @@ pub fn get_word_with_min_len(text: &str, stop_char: u8, min_length: usize) -> St
     String::from_utf8(word).unwrap()
 }
 
-pub fn get_word(text: &str, stop_char: u8) -> String {
-    get_word_with_min_len(text, stop_char, 0)
+pub fn get_word(text: &str, stop_char: u8, class: &CharClass) -> String {
+    get_word_with_min_len(text, stop_char, 0, class)
 }
 
 pub fn oneline(text: &str) -> &str {
@@ -106,21 +112,29 @@ mod test {
     use super::*;
     #[test]
     fn test_get_word() {
-        assert_eq!(get_word("print", 0), "print");
+        let none = CharClass::default();
+        assert_eq!(get_word("print", 0, &none), "print");
 
-        assert_eq!(get_word("$variable", 0), "$variable");
-        assert_eq!(get_word("print()", 0), "print");
-        assert_eq!(get_word("[\"cmp#confirm\"]", 0), "[\"cmp#confirm\"]");
-        assert_eq!(get_word("\"devDependencies\":", b'"'), "\"devDependencies");
+        assert_eq!(get_word("$variable", 0, &none), "$variable");
+        assert_eq!(get_word("print()", 0, &none), "print");
+        assert_eq!(get_word("[\"cmp#confirm\"]", 0, &none), "[\"cmp#confirm\"]");
         assert_eq!(
-            get_word("\"devDependencies\": ${1},", b'"'),
+            get_word("\"devDependencies\":", b'"', &none),
             "\"devDependencies"
         );
-        assert_eq!(get_word("#[cfg(test)]", 0), "#[cfg(test)]");
         assert_eq!(
-            get_word_with_min_len("import { GetStaticProps$1 } from \"next\";", 0, 9),
+            get_word("\"devDependencies\": ${1},", b'"', &none),
+            "\"devDependencies"
+        );
+        assert_eq!(get_word("#[cfg(test)]", 0, &none), "#[cfg(test)]");
+        assert_eq!(
+            get_word_with_min_len("import { GetStaticProps$1 } from \"next\";", 0, 9, &none),
             "import { GetStaticProps"
         );
+
+        let php = CharClass::new("$");
+        assert_eq!(get_word("foo$bar = 1", 0, &none), "foo");
+        assert_eq!(get_word("foo$bar = 1", 0, &php), "foo$bar");
     }
     #[test]
     fn test_remote_suffix() {
@@ -0,0 +1,100 @@
+//! Unicode counterparts of the helpers in `byte_char`, operating on scalar values.
+
+use unicode_normalization::char::decompose_canonical;
+
+pub fn is_white(c: char) -> bool {
+    c.is_whitespace()
+}
+
+pub fn is_upper(c: char) -> bool {
+    c.is_uppercase()
+}
+
+pub fn is_alpha(c: char) -> bool {
+    c.is_alphabetic()
+}
+
+pub fn is_digit(c: char) -> bool {
+    c.is_numeric()
+}
+
+pub fn is_alnum(c: char) -> bool {
+    is_alpha(c) || is_digit(c)
+}
+
+pub fn is_symbol(c: char) -> bool {
+    !(is_alnum(c) || is_white(c))
+}
+
+/// Simple case folding (the `C` + `S` mappings of `CaseFolding.txt`).
+///
+/// `char::to_lowercase` covers almost all of it; the exceptions are the letters whose
+/// folding differs from their lowercase form, such as the Greek final sigma.
+pub fn fold_case(c: char) -> char {
+    if c.is_ascii() {
+        return c.to_ascii_lowercase();
+    }
+    match c {
+        '\u{00b5}' => '\u{03bc}', // MICRO SIGN -> GREEK SMALL LETTER MU
+        '\u{017f}' => 's',        // LATIN SMALL LETTER LONG S
+        '\u{0345}' | '\u{1fbe}' => '\u{03b9}',
+        '\u{03c2}' => '\u{03c3}', // GREEK SMALL LETTER FINAL SIGMA
+        '\u{03d0}' => '\u{03b2}',
+        '\u{03d1}' => '\u{03b8}',
+        '\u{03d5}' => '\u{03c6}',
+        '\u{03d6}' => '\u{03c0}',
+        '\u{03f0}' => '\u{03ba}',
+        '\u{03f1}' => '\u{03c1}',
+        '\u{03f5}' => '\u{03b5}',
+        '\u{1e9b}' => '\u{1e61}',
+        '\u{1e9e}' => '\u{00df}', // LATIN CAPITAL LETTER SHARP S
+        _ => {
+            let mut lower = c.to_lowercase();
+            match (lower.next(), lower.next()) {
+                (Some(l), None) => l,
+                _ => c,
+            }
+        }
+    }
+}
+
+/// The base letter of a char with diacritics, from its canonical decomposition (`é` -> `e`).
+///
+/// Hangul syllables are kept whole, their decomposition is not a base letter and marks.
+pub fn fold_diacritics(c: char) -> char {
+    if c.is_ascii() || ('\u{ac00}'..='\u{d7a3}').contains(&c) {
+        return c;
+    }
+    let mut base = None;
+    decompose_canonical(c, |d| {
+        base.get_or_insert(d);
+    });
+    base.unwrap_or(c)
+}
+
+pub fn match_char(c1: char, c2: char) -> bool {
+    c1 == c2 || fold_case(c1) == fold_case(c2)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn basic_cases() {
+        assert!(match_char('a', 'A'));
+        assert!(match_char('Ä', 'ä'));
+        assert!(match_char('П', 'п'));
+        assert!(match_char('Σ', 'ς'));
+        assert!(match_char('ẞ', 'ß'));
+        assert!(!match_char('a', 'ä'));
+        assert!(!match_char('_', '-'));
+
+        assert_eq!(fold_diacritics('é'), 'e');
+        assert_eq!(fold_diacritics('Ü'), 'U');
+        assert_eq!(fold_diacritics('ï'), 'i');
+        assert_eq!(fold_diacritics('ǖ'), 'u');
+        assert_eq!(fold_diacritics('ß'), 'ß');
+        assert_eq!(fold_diacritics('한'), '한');
+        assert_eq!(fold_diacritics('a'), 'a');
+    }
+}